@@ -0,0 +1,295 @@
+use file_compression::{
+    codec::Codec, codecs, compress_stream_with, decompress_stream_with, DecompressionLimits,
+};
+use serde::Serialize;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Heap bytes currently allocated.
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+/// Highest value `CURRENT` reached since the last [`reset_peak`].
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// System allocator that keeps track of current and peak heap use.
+struct PeakAllocator;
+
+impl PeakAllocator {
+    fn grew(size: usize) {
+        let now = CURRENT.fetch_add(size, Ordering::Relaxed) + size;
+        PEAK.fetch_max(now, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for PeakAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            Self::grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+            Self::grew(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAllocator = PeakAllocator;
+
+/// Starts a new peak measurement and returns the current heap use.
+fn reset_peak() -> usize {
+    let now = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(now, Ordering::Relaxed);
+    now
+}
+
+/// Peak heap use above `baseline` since the last [`reset_peak`].
+fn peak_since(baseline: usize) -> usize {
+    PEAK.load(Ordering::Relaxed).saturating_sub(baseline)
+}
+
+#[derive(Serialize)]
+struct Corpus {
+    directory: PathBuf,
+    files: usize,
+    bytes: u64,
+}
+
+/// Measurements for one codec and level over the whole corpus.
+#[derive(Serialize)]
+struct BenchResult {
+    codec: &'static str,
+    level: u32,
+    original_bytes: u64,
+    compressed_bytes: u64,
+    /// Compressed size over original size.
+    ratio: f64,
+    /// Original bytes compressed per second, in MB/s.
+    compress_mb_per_s: f64,
+    /// Original bytes produced per second while decompressing, in MB/s.
+    decompress_mb_per_s: f64,
+    /// Largest heap use of any single compression, excluding the output.
+    compress_peak_bytes: usize,
+    /// Largest heap use of any single decompression, excluding the output.
+    decompress_peak_bytes: usize,
+}
+
+#[derive(Serialize)]
+struct Report {
+    corpus: Corpus,
+    results: Vec<BenchResult>,
+}
+
+/// Reads every regular file under `dir`, recursively, in path order.
+fn load_corpus(dir: &Path, files: &mut Vec<Vec<u8>>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.path());
+
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            load_corpus(&entry.path(), files)?;
+        } else if file_type.is_file() {
+            files.push(fs::read(entry.path())?);
+        }
+    }
+    Ok(())
+}
+
+fn mb_per_s(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 / 1_000_000.0 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+/// Compresses and decompresses every file separately, as the server does.
+fn bench(codec: &dyn Codec, level: u32, files: &[Vec<u8>]) -> io::Result<BenchResult> {
+    let mut original_bytes = 0u64;
+    let mut compressed_bytes = 0u64;
+    let mut compress_time = Duration::ZERO;
+    let mut decompress_time = Duration::ZERO;
+    let mut compress_peak = 0;
+    let mut decompress_peak = 0;
+
+    for file in files {
+        // Outputs are allocated up front so they do not count as codec memory
+        let mut compressed = Vec::with_capacity(file.len() + file.len() / 8 + 1024);
+        let baseline = reset_peak();
+        let started = Instant::now();
+        compress_stream_with(codec, &file[..], &mut compressed, level)?;
+        compress_time += started.elapsed();
+        compress_peak = compress_peak.max(peak_since(baseline));
+
+        let mut decompressed = Vec::with_capacity(file.len());
+        let baseline = reset_peak();
+        let started = Instant::now();
+        decompress_stream_with(
+            codec,
+            &compressed[..],
+            &mut decompressed,
+            &DecompressionLimits::unlimited(),
+        )?;
+        decompress_time += started.elapsed();
+        decompress_peak = decompress_peak.max(peak_since(baseline));
+
+        if decompressed != *file {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} level {} did not round-trip", codec.name(), level),
+            ));
+        }
+        original_bytes += file.len() as u64;
+        compressed_bytes += compressed.len() as u64;
+    }
+
+    Ok(BenchResult {
+        codec: codec.name(),
+        level,
+        original_bytes,
+        compressed_bytes,
+        ratio: compressed_bytes as f64 / original_bytes.max(1) as f64,
+        compress_mb_per_s: mb_per_s(original_bytes, compress_time),
+        decompress_mb_per_s: mb_per_s(original_bytes, decompress_time),
+        compress_peak_bytes: compress_peak,
+        decompress_peak_bytes: decompress_peak,
+    })
+}
+
+fn format_bytes(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn print_table(report: &Report) {
+    println!(
+        "{} files, {} bytes in {}",
+        report.corpus.files,
+        report.corpus.bytes,
+        report.corpus.directory.display()
+    );
+    println!(
+        "{:<8} {:>5} {:>8} {:>14} {:>16} {:>14} {:>16}",
+        "codec",
+        "level",
+        "ratio",
+        "compress MB/s",
+        "decompress MB/s",
+        "compress peak",
+        "decompress peak"
+    );
+    for result in &report.results {
+        println!(
+            "{:<8} {:>5} {:>8.4} {:>14.1} {:>16.1} {:>14} {:>16}",
+            result.codec,
+            result.level,
+            result.ratio,
+            result.compress_mb_per_s,
+            result.decompress_mb_per_s,
+            format_bytes(result.compress_peak_bytes),
+            format_bytes(result.decompress_peak_bytes)
+        );
+    }
+}
+
+fn usage(program: &str) -> ! {
+    eprintln!(
+        "Usage: {} [--json] [--codec <name>]... <corpus_dir>\n\
+         Runs every codec and level over the files in <corpus_dir>. --codec limits the run\n\
+         to the named codecs; --json prints JSON instead of a table.",
+        program
+    );
+    process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut json = false;
+    let mut selected: Vec<&'static dyn Codec> = Vec::new();
+    let mut directory = None;
+
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--codec" => {
+                let name = rest.next().unwrap_or_else(|| usage(&args[0]));
+                match file_compression::codec_by_name(name) {
+                    Some(codec) => selected.push(codec),
+                    None => {
+                        eprintln!("Unknown codec: {}", name);
+                        process::exit(2);
+                    }
+                }
+            }
+            _ if directory.is_none() && !arg.starts_with("--") => {
+                directory = Some(PathBuf::from(arg))
+            }
+            _ => usage(&args[0]),
+        }
+    }
+    let directory = directory.unwrap_or_else(|| usage(&args[0]));
+    if selected.is_empty() {
+        selected = codecs().to_vec();
+    }
+
+    let mut files = Vec::new();
+    if let Err(e) = load_corpus(&directory, &mut files) {
+        eprintln!("Failed to read {}: {}", directory.display(), e);
+        process::exit(1);
+    }
+    if files.is_empty() {
+        eprintln!("No files found in {}", directory.display());
+        process::exit(1);
+    }
+
+    let mut results = Vec::new();
+    for codec in selected {
+        for level in codec.levels() {
+            match bench(codec, level, &files) {
+                Ok(result) => results.push(result),
+                Err(e) => {
+                    eprintln!("{} level {} failed: {}", codec.name(), level, e);
+                    process::exit(1);
+                }
+            }
+        }
+    }
+
+    let report = Report {
+        corpus: Corpus {
+            directory,
+            files: files.len(),
+            bytes: files.iter().map(|file| file.len() as u64).sum(),
+        },
+        results,
+    };
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("report serializes")
+        );
+    } else {
+        print_table(&report);
+    }
+}
@@ -0,0 +1,134 @@
+#ifndef FILE_COMPRESSION_H
+#define FILE_COMPRESSION_H
+
+/* Generated by cbindgen from src/lib.rs. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Pass as the level to use the codec's default level.
+#define FC_DEFAULT_LEVEL UINT32_MAX
+
+// Result of every `fc_` function. On anything but `Ok`,
+// [`fc_last_error`] describes what went wrong.
+typedef enum FcStatus {
+  FC_STATUS_OK = 0,
+  // A required pointer was NULL or a string was not valid UTF-8.
+  FC_STATUS_INVALID_ARGUMENT = 1,
+  // The codec name is unknown or not compiled into this build.
+  FC_STATUS_UNKNOWN_CODEC = 2,
+  // The level is outside the codec's range.
+  FC_STATUS_INVALID_LEVEL = 3,
+  // The compressed input is corrupt or truncated.
+  FC_STATUS_CORRUPT_DATA = 4,
+  // Decompressed output grew past the default decompression limits.
+  FC_STATUS_LIMIT_EXCEEDED = 5,
+  // A read or write callback reported an error.
+  FC_STATUS_IO_ERROR = 6,
+  // The library panicked. The handle involved must only be freed.
+  FC_STATUS_PANIC = 7,
+} FcStatus;
+
+// Opaque decompression handle.
+typedef struct FcDecoder FcDecoder;
+
+// Opaque compression handle.
+typedef struct FcEncoder FcEncoder;
+
+// Write callback for compressed output. Returns the number of bytes
+// consumed from `data`, which must be more than zero when `len` is, or a
+// negative value on error.
+typedef ptrdiff_t (*FcWriteFn)(void *ctx, const uint8_t *data, size_t len);
+
+// Read callback for compressed input. Fills up to `len` bytes of `buf`
+// and returns how many were read, 0 at the end of the input, or a negative
+// value on error.
+typedef ptrdiff_t (*FcReadFn)(void *ctx, uint8_t *buf, size_t len);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Message describing the last failed call on this thread, or NULL. The
+// string stays valid until the next failing call on the same thread.
+const char *fc_last_error(void);
+
+// Starts compressing with `codec` (e.g. `"gzip"`) at `level`, or
+// [`FC_DEFAULT_LEVEL`]. Compressed bytes are passed to `write` as they are
+// produced. On success `*out` holds a handle that must be passed to
+// [`fc_encoder_finish`] or [`fc_encoder_free`].
+//
+// # Safety
+//
+// `codec` must be a NUL-terminated string and `out` a valid pointer.
+// `write` must be safe to call with `ctx` until the handle is released.
+enum FcStatus fc_encoder_new(const char *codec,
+                             uint32_t level,
+                             FcWriteFn write,
+                             void *ctx,
+                             struct FcEncoder **out);
+
+// Compresses `len` bytes from `data`.
+//
+// # Safety
+//
+// `encoder` must come from [`fc_encoder_new`] and not have been released,
+// and `data` must point to `len` readable bytes (it may be NULL if `len`
+// is 0).
+enum FcStatus fc_encoder_write(struct FcEncoder *encoder, const uint8_t *data, size_t len);
+
+// Writes the rest of the compressed stream and releases the handle,
+// whether or not finishing succeeds.
+//
+// # Safety
+//
+// `encoder` must come from [`fc_encoder_new`] and not have been released.
+enum FcStatus fc_encoder_finish(struct FcEncoder *encoder);
+
+// Releases an encoder without finishing its stream. NULL is ignored.
+//
+// # Safety
+//
+// `encoder` must be NULL or come from [`fc_encoder_new`] and not have been
+// released.
+void fc_encoder_free(struct FcEncoder *encoder);
+
+// Starts decompressing a `codec` stream pulled from `read`, with the
+// library's default decompression limits. On success `*out` holds a handle
+// that must be passed to [`fc_decoder_free`].
+//
+// # Safety
+//
+// `codec` must be a NUL-terminated string and `out` a valid pointer.
+// `read` must be safe to call with `ctx` until the handle is released.
+enum FcStatus fc_decoder_new(const char *codec, FcReadFn read, void *ctx, struct FcDecoder **out);
+
+// Decompresses up to `capacity` bytes into `buf` and stores how many were
+// written in `*read`. A count of 0 means the stream has ended.
+//
+// # Safety
+//
+// `decoder` must come from [`fc_decoder_new`] and not have been released,
+// `buf` must point to `capacity` writable bytes and `read` must be a valid
+// pointer.
+enum FcStatus fc_decoder_read(struct FcDecoder *decoder,
+                              uint8_t *buf,
+                              size_t capacity,
+                              size_t *read);
+
+// Releases a decoder. NULL is ignored.
+//
+// # Safety
+//
+// `decoder` must be NULL or come from [`fc_decoder_new`] and not have been
+// released.
+void fc_decoder_free(struct FcDecoder *decoder);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* FILE_COMPRESSION_H */
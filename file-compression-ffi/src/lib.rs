@@ -0,0 +1,576 @@
+use file_compression::codec::{codec_by_name, Encoder};
+use file_compression::decompress::{DecompressionLimits, LimitedDecoder};
+use file_compression::error::CompressionError;
+use file_compression::options::CompressionOptions;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::io::{self, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::{ptr, slice};
+
+/// Pass as the level to use the codec's default level.
+pub const FC_DEFAULT_LEVEL: u32 = u32::MAX;
+
+/// Result of every `fc_` function. On anything but `Ok`,
+/// [`fc_last_error`] describes what went wrong.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FcStatus {
+    Ok = 0,
+    /// A required pointer was NULL or a string was not valid UTF-8.
+    InvalidArgument = 1,
+    /// The codec name is unknown or not compiled into this build.
+    UnknownCodec = 2,
+    /// The level is outside the codec's range.
+    InvalidLevel = 3,
+    /// The compressed input is corrupt or truncated.
+    CorruptData = 4,
+    /// Decompressed output grew past the default decompression limits.
+    LimitExceeded = 5,
+    /// A read or write callback reported an error.
+    IoError = 6,
+    /// The library panicked. The handle involved must only be freed.
+    Panic = 7,
+}
+
+/// Write callback for compressed output. Returns the number of bytes
+/// consumed from `data`, which must be more than zero when `len` is, or a
+/// negative value on error.
+pub type FcWriteFn =
+    Option<unsafe extern "C" fn(ctx: *mut c_void, data: *const u8, len: usize) -> isize>;
+
+/// Read callback for compressed input. Fills up to `len` bytes of `buf`
+/// and returns how many were read, 0 at the end of the input, or a negative
+/// value on error.
+pub type FcReadFn =
+    Option<unsafe extern "C" fn(ctx: *mut c_void, buf: *mut u8, len: usize) -> isize>;
+
+/// Opaque compression handle.
+pub struct FcEncoder {
+    encoder: Box<dyn Encoder>,
+}
+
+/// Opaque decompression handle.
+pub struct FcDecoder {
+    codec: &'static str,
+    decoder: LimitedDecoder<'static>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).expect("NUL bytes were replaced");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+fn status_for(error: &CompressionError) -> FcStatus {
+    match error {
+        CompressionError::InvalidOption {
+            option: "codec", ..
+        } => FcStatus::UnknownCodec,
+        CompressionError::InvalidOption {
+            option: "level", ..
+        } => FcStatus::InvalidLevel,
+        CompressionError::InvalidOption { .. } => FcStatus::InvalidArgument,
+        CompressionError::LimitExceeded(_) => FcStatus::LimitExceeded,
+        CompressionError::Codec { .. } => FcStatus::CorruptData,
+        CompressionError::Io(source) if source.kind() == io::ErrorKind::InvalidData => {
+            FcStatus::CorruptData
+        }
+        _ => FcStatus::IoError,
+    }
+}
+
+/// Runs one API call, turning errors and panics into a status code so
+/// neither crosses the C boundary.
+fn guard(call: impl FnOnce() -> Result<(), CompressionError>) -> FcStatus {
+    match panic::catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(())) => FcStatus::Ok,
+        Ok(Err(e)) => {
+            let status = status_for(&e);
+            set_last_error(e.to_string());
+            status
+        }
+        Err(_) => {
+            set_last_error("internal error in file-compression".to_string());
+            FcStatus::Panic
+        }
+    }
+}
+
+fn invalid_argument(message: &str) -> CompressionError {
+    CompressionError::InvalidOption {
+        option: "argument",
+        message: message.to_string(),
+    }
+}
+
+/// Borrows a C string argument.
+///
+/// # Safety
+///
+/// `value` must be NULL or point to a NUL-terminated string.
+unsafe fn c_str<'a>(value: *const c_char, name: &str) -> Result<&'a str, CompressionError> {
+    if value.is_null() {
+        return Err(invalid_argument(&format!("{} is NULL", name)));
+    }
+    CStr::from_ptr(value)
+        .to_str()
+        .map_err(|_| invalid_argument(&format!("{} is not valid UTF-8", name)))
+}
+
+/// [`Write`] over a C callback.
+struct CallbackWriter {
+    write: unsafe extern "C" fn(*mut c_void, *const u8, usize) -> isize,
+    ctx: *mut c_void,
+}
+
+impl Write for CallbackWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // SAFETY: the caller of `fc_encoder_new` vouched for the callback
+        // and its context
+        let written = unsafe { (self.write)(self.ctx, buf.as_ptr(), buf.len()) };
+        match usize::try_from(written) {
+            Ok(written) if written <= buf.len() => Ok(written),
+            _ => Err(CompressionError::Write {
+                path: None,
+                source: io::Error::other("write callback failed"),
+            }
+            .into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// [`Read`] over a C callback.
+struct CallbackReader {
+    read: unsafe extern "C" fn(*mut c_void, *mut u8, usize) -> isize,
+    ctx: *mut c_void,
+}
+
+impl Read for CallbackReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // SAFETY: the caller of `fc_decoder_new` vouched for the callback
+        // and its context
+        let read = unsafe { (self.read)(self.ctx, buf.as_mut_ptr(), buf.len()) };
+        match usize::try_from(read) {
+            Ok(read) if read <= buf.len() => Ok(read),
+            _ => Err(CompressionError::Read {
+                path: None,
+                source: io::Error::other("read callback failed"),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Message describing the last failed call on this thread, or NULL. The
+/// string stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn fc_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// Starts compressing with `codec` (e.g. `"gzip"`) at `level`, or
+/// [`FC_DEFAULT_LEVEL`]. Compressed bytes are passed to `write` as they are
+/// produced. On success `*out` holds a handle that must be passed to
+/// [`fc_encoder_finish`] or [`fc_encoder_free`].
+///
+/// # Safety
+///
+/// `codec` must be a NUL-terminated string and `out` a valid pointer.
+/// `write` must be safe to call with `ctx` until the handle is released.
+#[no_mangle]
+pub unsafe extern "C" fn fc_encoder_new(
+    codec: *const c_char,
+    level: u32,
+    write: FcWriteFn,
+    ctx: *mut c_void,
+    out: *mut *mut FcEncoder,
+) -> FcStatus {
+    guard(|| {
+        let out = out
+            .as_mut()
+            .ok_or_else(|| invalid_argument("out is NULL"))?;
+        let write = write.ok_or_else(|| invalid_argument("write callback is NULL"))?;
+
+        let mut builder = CompressionOptions::builder().codec_name(c_str(codec, "codec")?);
+        if level != FC_DEFAULT_LEVEL {
+            builder = builder.level(level);
+        }
+        let options = builder.build()?;
+        let encoder = options
+            .codec()
+            .encoder(Box::new(CallbackWriter { write, ctx }), options.level())?;
+
+        *out = Box::into_raw(Box::new(FcEncoder { encoder }));
+        Ok(())
+    })
+}
+
+/// Compresses `len` bytes from `data`.
+///
+/// # Safety
+///
+/// `encoder` must come from [`fc_encoder_new`] and not have been released,
+/// and `data` must point to `len` readable bytes (it may be NULL if `len`
+/// is 0).
+#[no_mangle]
+pub unsafe extern "C" fn fc_encoder_write(
+    encoder: *mut FcEncoder,
+    data: *const u8,
+    len: usize,
+) -> FcStatus {
+    guard(|| {
+        let encoder = encoder
+            .as_mut()
+            .ok_or_else(|| invalid_argument("encoder is NULL"))?;
+        if len == 0 {
+            return Ok(());
+        }
+        if data.is_null() {
+            return Err(invalid_argument("data is NULL"));
+        }
+        encoder
+            .encoder
+            .write_all(slice::from_raw_parts(data, len))?;
+        Ok(())
+    })
+}
+
+/// Writes the rest of the compressed stream and releases the handle,
+/// whether or not finishing succeeds.
+///
+/// # Safety
+///
+/// `encoder` must come from [`fc_encoder_new`] and not have been released.
+#[no_mangle]
+pub unsafe extern "C" fn fc_encoder_finish(encoder: *mut FcEncoder) -> FcStatus {
+    guard(|| {
+        if encoder.is_null() {
+            return Err(invalid_argument("encoder is NULL"));
+        }
+        let handle = Box::from_raw(encoder);
+        handle.encoder.finish()?;
+        Ok(())
+    })
+}
+
+/// Releases an encoder without finishing its stream. NULL is ignored.
+///
+/// # Safety
+///
+/// `encoder` must be NULL or come from [`fc_encoder_new`] and not have been
+/// released.
+#[no_mangle]
+pub unsafe extern "C" fn fc_encoder_free(encoder: *mut FcEncoder) {
+    if !encoder.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(encoder))));
+    }
+}
+
+/// Starts decompressing a `codec` stream pulled from `read`, with the
+/// library's default decompression limits. On success `*out` holds a handle
+/// that must be passed to [`fc_decoder_free`].
+///
+/// # Safety
+///
+/// `codec` must be a NUL-terminated string and `out` a valid pointer.
+/// `read` must be safe to call with `ctx` until the handle is released.
+#[no_mangle]
+pub unsafe extern "C" fn fc_decoder_new(
+    codec: *const c_char,
+    read: FcReadFn,
+    ctx: *mut c_void,
+    out: *mut *mut FcDecoder,
+) -> FcStatus {
+    guard(|| {
+        let out = out
+            .as_mut()
+            .ok_or_else(|| invalid_argument("out is NULL"))?;
+        let read = read.ok_or_else(|| invalid_argument("read callback is NULL"))?;
+        let name = c_str(codec, "codec")?;
+        let codec = codec_by_name(name).ok_or_else(|| CompressionError::InvalidOption {
+            option: "codec",
+            message: format!("{} is not available in this build", name),
+        })?;
+
+        let decoder = LimitedDecoder::with_codec(
+            codec,
+            CallbackReader { read, ctx },
+            DecompressionLimits::default(),
+        )?;
+        *out = Box::into_raw(Box::new(FcDecoder {
+            codec: codec.name(),
+            decoder,
+        }));
+        Ok(())
+    })
+}
+
+/// Decompresses up to `capacity` bytes into `buf` and stores how many were
+/// written in `*read`. A count of 0 means the stream has ended.
+///
+/// # Safety
+///
+/// `decoder` must come from [`fc_decoder_new`] and not have been released,
+/// `buf` must point to `capacity` writable bytes and `read` must be a valid
+/// pointer.
+#[no_mangle]
+pub unsafe extern "C" fn fc_decoder_read(
+    decoder: *mut FcDecoder,
+    buf: *mut u8,
+    capacity: usize,
+    read: *mut usize,
+) -> FcStatus {
+    guard(|| {
+        let decoder = decoder
+            .as_mut()
+            .ok_or_else(|| invalid_argument("decoder is NULL"))?;
+        let read = read
+            .as_mut()
+            .ok_or_else(|| invalid_argument("read is NULL"))?;
+        if buf.is_null() || capacity == 0 {
+            return Err(invalid_argument("buf is NULL or empty"));
+        }
+
+        let buf = slice::from_raw_parts_mut(buf, capacity);
+        *read = loop {
+            match decoder.decoder.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        }
+        // Anything the read callback did not report came from the codec
+        .map_err(|e| match CompressionError::from(e) {
+            CompressionError::Io(source) => CompressionError::Codec {
+                codec: decoder.codec.to_string(),
+                source,
+            },
+            error => error,
+        })?;
+        Ok(())
+    })
+}
+
+/// Releases a decoder. NULL is ignored.
+///
+/// # Safety
+///
+/// `decoder` must be NULL or come from [`fc_decoder_new`] and not have been
+/// released.
+#[no_mangle]
+pub unsafe extern "C" fn fc_decoder_free(decoder: *mut FcDecoder) {
+    if !decoder.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(decoder))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FcReadFn, FcStatus, FcWriteFn, FC_DEFAULT_LEVEL};
+    use std::ffi::{c_char, c_void, CStr};
+    use std::ptr;
+
+    // Handles are opaque to C, which only ever holds pointers to them
+    #[repr(C)]
+    struct FcEncoder {
+        _private: [u8; 0],
+    }
+
+    #[repr(C)]
+    struct FcDecoder {
+        _private: [u8; 0],
+    }
+
+    // Declared the way a C caller sees them, so the tests go through the
+    // exported symbols rather than the Rust functions
+    extern "C" {
+        fn fc_last_error() -> *const c_char;
+        fn fc_encoder_new(
+            codec: *const c_char,
+            level: u32,
+            write: FcWriteFn,
+            ctx: *mut c_void,
+            out: *mut *mut FcEncoder,
+        ) -> FcStatus;
+        fn fc_encoder_write(encoder: *mut FcEncoder, data: *const u8, len: usize) -> FcStatus;
+        fn fc_encoder_finish(encoder: *mut FcEncoder) -> FcStatus;
+        fn fc_encoder_free(encoder: *mut FcEncoder);
+        fn fc_decoder_new(
+            codec: *const c_char,
+            read: FcReadFn,
+            ctx: *mut c_void,
+            out: *mut *mut FcDecoder,
+        ) -> FcStatus;
+        fn fc_decoder_read(
+            decoder: *mut FcDecoder,
+            buf: *mut u8,
+            capacity: usize,
+            read: *mut usize,
+        ) -> FcStatus;
+        fn fc_decoder_free(decoder: *mut FcDecoder);
+    }
+
+    unsafe extern "C" fn write_to_vec(ctx: *mut c_void, data: *const u8, len: usize) -> isize {
+        let output = &mut *(ctx as *mut Vec<u8>);
+        output.extend_from_slice(std::slice::from_raw_parts(data, len));
+        len as isize
+    }
+
+    unsafe extern "C" fn failing_write(_: *mut c_void, _: *const u8, _: usize) -> isize {
+        -1
+    }
+
+    /// Input for [`read_from_slice`]: the data and how much has been read.
+    struct Source<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    unsafe extern "C" fn read_from_slice(ctx: *mut c_void, buf: *mut u8, len: usize) -> isize {
+        let source = &mut *(ctx as *mut Source);
+        // Short reads, to exercise the decoder's buffering
+        let n = len.min(7).min(source.data.len() - source.position);
+        ptr::copy_nonoverlapping(source.data[source.position..].as_ptr(), buf, n);
+        source.position += n;
+        n as isize
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(fc_last_error()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn compress(codec: &CStr, level: u32, data: &[u8]) -> Result<Vec<u8>, FcStatus> {
+        let mut output = Vec::new();
+        let mut encoder = ptr::null_mut();
+        unsafe {
+            let status = fc_encoder_new(
+                codec.as_ptr(),
+                level,
+                Some(write_to_vec),
+                &mut output as *mut Vec<u8> as *mut c_void,
+                &mut encoder,
+            );
+            if status != FcStatus::Ok {
+                return Err(status);
+            }
+            for chunk in data.chunks(1000) {
+                let status = fc_encoder_write(encoder, chunk.as_ptr(), chunk.len());
+                if status != FcStatus::Ok {
+                    fc_encoder_free(encoder);
+                    return Err(status);
+                }
+            }
+            match fc_encoder_finish(encoder) {
+                FcStatus::Ok => Ok(output),
+                status => Err(status),
+            }
+        }
+    }
+
+    fn decompress(codec: &CStr, data: &[u8]) -> Result<Vec<u8>, FcStatus> {
+        let mut source = Source { data, position: 0 };
+        let mut decoder = ptr::null_mut();
+        let mut output = Vec::new();
+        unsafe {
+            let status = fc_decoder_new(
+                codec.as_ptr(),
+                Some(read_from_slice),
+                &mut source as *mut Source as *mut c_void,
+                &mut decoder,
+            );
+            if status != FcStatus::Ok {
+                return Err(status);
+            }
+            let mut buf = [0u8; 512];
+            let result = loop {
+                let mut read = 0;
+                match fc_decoder_read(decoder, buf.as_mut_ptr(), buf.len(), &mut read) {
+                    FcStatus::Ok if read == 0 => break Ok(output),
+                    FcStatus::Ok => output.extend_from_slice(&buf[..read]),
+                    status => break Err(status),
+                }
+            };
+            fc_decoder_free(decoder);
+            result
+        }
+    }
+
+    #[test]
+    fn test_round_trip_through_c_abi() {
+        let data = b"called from C, compressed in Rust. ".repeat(300);
+        for codec in file_compression::codecs() {
+            let name = std::ffi::CString::new(codec.name()).unwrap();
+            let compressed = compress(&name, FC_DEFAULT_LEVEL, &data).unwrap();
+            assert!(compressed.len() < data.len(), "{}", codec.name());
+            assert_eq!(decompress(&name, &compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_errors_become_status_codes() {
+        assert_eq!(compress(c"gzip", 42, b"x"), Err(FcStatus::InvalidLevel));
+        assert!(last_error().contains("level"));
+        assert_eq!(
+            compress(c"nope", FC_DEFAULT_LEVEL, b"x"),
+            Err(FcStatus::UnknownCodec)
+        );
+        assert_eq!(decompress(c"gzip", b"not gzip"), Err(FcStatus::CorruptData));
+
+        let mut encoder = ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                fc_encoder_new(
+                    ptr::null(),
+                    FC_DEFAULT_LEVEL,
+                    Some(write_to_vec),
+                    ptr::null_mut(),
+                    &mut encoder,
+                ),
+                FcStatus::InvalidArgument
+            );
+            assert!(encoder.is_null());
+
+            // A failing callback surfaces once the encoder has to write
+            assert_eq!(
+                fc_encoder_new(
+                    c"gzip".as_ptr(),
+                    FC_DEFAULT_LEVEL,
+                    Some(failing_write),
+                    ptr::null_mut(),
+                    &mut encoder,
+                ),
+                FcStatus::Ok
+            );
+            assert_eq!(fc_encoder_finish(encoder), FcStatus::IoError);
+            assert!(last_error().contains("write callback failed"));
+        }
+    }
+
+    #[test]
+    fn header_is_up_to_date() {
+        let crate_dir = env!("CARGO_MANIFEST_DIR");
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+        let mut generated = Vec::new();
+        cbindgen::Builder::new()
+            .with_crate(crate_dir)
+            .with_config(config)
+            .generate()
+            .unwrap()
+            .write(&mut generated);
+
+        let committed = std::fs::read(format!("{}/include/file_compression.h", crate_dir)).unwrap();
+        assert!(
+            generated == committed,
+            "include/file_compression.h is out of date, regenerate it with cbindgen"
+        );
+    }
+}
@@ -0,0 +1,759 @@
+use crate::codec::{codec_by_extension, codec_by_name, Codec, Gzip};
+use crate::decompress::{DecompressionLimits, LimitExceeded, LimitedDecoder, RATIO_GRACE_BYTES};
+use filetime::FileTime;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Container format for [`create_archive`], [`list_archive`] and
+/// [`extract_archive`].
+#[derive(Clone, Copy)]
+pub enum ArchiveFormat {
+    /// A tar stream, compressed with the given codec or left uncompressed.
+    Tar(Option<&'static dyn Codec>),
+    /// A zip file with deflate-compressed entries.
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Guesses the format from a file name such as `batch.tar.zst`,
+    /// `batch.tgz` or `batch.zip`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+
+        if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveFormat::Tar(None))
+        } else if name.ends_with(".tgz") {
+            Some(ArchiveFormat::Tar(codec_by_name("gzip")))
+        } else {
+            let (stem, extension) = name.rsplit_once('.')?;
+            if !stem.ends_with(".tar") {
+                return None;
+            }
+            codec_by_extension(extension).map(|codec| ArchiveFormat::Tar(Some(codec)))
+        }
+    }
+}
+
+impl fmt::Debug for ArchiveFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArchiveFormat::Tar(None) => write!(f, "Tar"),
+            ArchiveFormat::Tar(Some(codec)) => write!(f, "Tar({})", codec.name()),
+            ArchiveFormat::Zip => write!(f, "Zip"),
+        }
+    }
+}
+
+/// One file or directory stored in an archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Relative path inside the archive.
+    pub path: PathBuf,
+    /// Uncompressed size in bytes, zero for directories.
+    pub size: u64,
+    pub is_dir: bool,
+    /// Unix permission bits, if the archive records them.
+    pub mode: Option<u32>,
+    /// Modification time in seconds since the Unix epoch, if recorded.
+    pub mtime: Option<u64>,
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+fn file_mtime(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// Walks `source` depth-first, pairing every path with its name in the
+/// archive. Children are sorted so archives are reproducible.
+fn collect_sources(
+    source: &Path,
+    name: PathBuf,
+    out: &mut Vec<(PathBuf, PathBuf, Metadata)>,
+) -> io::Result<()> {
+    let metadata = fs::metadata(source)?;
+    let is_dir = metadata.is_dir();
+    out.push((source.to_path_buf(), name.clone(), metadata));
+
+    if is_dir {
+        let mut children = fs::read_dir(source)?.collect::<io::Result<Vec<_>>>()?;
+        children.sort_by_key(|child| child.file_name());
+        for child in children {
+            collect_sources(&child.path(), name.join(child.file_name()), out)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Rejects absolute paths and `..` components so an entry can never be
+/// written outside the extraction directory ("zip-slip").
+fn safe_relative_path(name: &Path) -> io::Result<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "archive entry {} would escape the destination",
+                        name.display()
+                    ),
+                ))
+            }
+        }
+    }
+
+    if path.as_os_str().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "archive entry has an empty path",
+        ));
+    }
+    Ok(path)
+}
+
+/// Joins path components with `/`, as zip entry names require.
+fn zip_name(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Converts seconds since the Unix epoch (UTC) into an MS-DOS timestamp.
+/// Zip timestamps have two-second resolution and start in 1980.
+fn dos_datetime(unix_seconds: u64) -> zip::DateTime {
+    let (year, month, day) = civil_from_days((unix_seconds / 86_400) as i64);
+    let seconds = unix_seconds % 86_400;
+    u16::try_from(year)
+        .ok()
+        .and_then(|year| {
+            zip::DateTime::from_date_and_time(
+                year,
+                month,
+                day,
+                (seconds / 3_600) as u8,
+                (seconds % 3_600 / 60) as u8,
+                (seconds % 60) as u8 & !1,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
+fn unix_seconds(datetime: zip::DateTime) -> u64 {
+    let days = days_from_civil(datetime.year() as i64, datetime.month(), datetime.day());
+    let seconds =
+        datetime.hour() as i64 * 3_600 + datetime.minute() as i64 * 60 + datetime.second() as i64;
+    (days * 86_400 + seconds).max(0) as u64
+}
+
+/// Days since 1970-01-01 to a proleptic Gregorian (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Inverse of [`civil_from_days`].
+fn days_from_civil(year: i64, month: u8, day: u8) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * if month > 2 { month - 3 } else { month + 9 } + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Bundles files and directory trees into a new archive at `output_file`.
+///
+/// Each source is stored under its own file name; directories are added
+/// recursively. File modes and modification times are recorded. When
+/// `compression_level` is `None` the codec's default level is used.
+pub fn create_archive<P: AsRef<Path>>(
+    output_file: &Path,
+    format: ArchiveFormat,
+    compression_level: Option<u32>,
+    sources: &[P],
+) -> io::Result<Vec<ArchiveEntry>> {
+    let mut collected = Vec::new();
+    for source in sources {
+        let source = source.as_ref();
+        let name = fs::canonicalize(source)?
+            .file_name()
+            .map(PathBuf::from)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("cannot archive {} without a file name", source.display()),
+                )
+            })?;
+        collect_sources(source, name, &mut collected)?;
+    }
+
+    let mut seen = HashSet::new();
+    let mut entries = Vec::with_capacity(collected.len());
+    for (_, name, metadata) in &collected {
+        if !seen.insert(name.clone()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("duplicate archive entry {}", name.display()),
+            ));
+        }
+        entries.push(ArchiveEntry {
+            path: name.clone(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            is_dir: metadata.is_dir(),
+            mode: Some(file_mode(metadata)),
+            mtime: Some(file_mtime(metadata)),
+        });
+    }
+
+    let output = BufWriter::new(File::create(output_file)?);
+    match format {
+        ArchiveFormat::Tar(None) => write_tar(output, &collected)?.flush()?,
+        ArchiveFormat::Tar(Some(codec)) => {
+            let level = compression_level.unwrap_or_else(|| codec.default_level());
+            let encoder = codec.encoder(Box::new(output), level)?;
+            write_tar(encoder, &collected)?.finish()?
+        }
+        ArchiveFormat::Zip => write_zip(
+            output,
+            compression_level.unwrap_or(Gzip.default_level()),
+            &collected,
+        )?,
+    }
+
+    Ok(entries)
+}
+
+fn write_tar<W: Write>(writer: W, sources: &[(PathBuf, PathBuf, Metadata)]) -> io::Result<W> {
+    let mut builder = tar::Builder::new(writer);
+    for (source, name, _) in sources {
+        builder.append_path_with_name(source, name)?;
+    }
+    builder.into_inner()
+}
+
+fn write_zip<W: Write + Seek>(
+    writer: W,
+    compression_level: u32,
+    sources: &[(PathBuf, PathBuf, Metadata)],
+) -> io::Result<()> {
+    Gzip.validate_level(compression_level)?;
+    let method = if compression_level == 0 {
+        CompressionMethod::Stored
+    } else {
+        CompressionMethod::Deflated
+    };
+
+    let mut zip = ZipWriter::new(writer);
+    for (source, name, metadata) in sources {
+        let mut options = SimpleFileOptions::default()
+            .compression_method(method)
+            .unix_permissions(file_mode(metadata))
+            .last_modified_time(dos_datetime(file_mtime(metadata)));
+        if method == CompressionMethod::Deflated {
+            options = options.compression_level(Some(compression_level as i64));
+        }
+
+        if metadata.is_dir() {
+            zip.add_directory(zip_name(name), options)?;
+        } else {
+            zip.start_file(zip_name(name), options)?;
+            io::copy(&mut File::open(source)?, &mut zip)?;
+        }
+    }
+    zip.finish()?.flush()
+}
+
+fn open_tar(
+    file: BufReader<File>,
+    codec: Option<&dyn Codec>,
+    limits: &DecompressionLimits,
+) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    let reader: Box<dyn Read> = match codec {
+        Some(codec) => Box::new(LimitedDecoder::with_codec(codec, file, *limits)?),
+        None => Box::new(file),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// Lists the entries of an archive without extracting it.
+pub fn list_archive(archive_file: &Path, format: ArchiveFormat) -> io::Result<Vec<ArchiveEntry>> {
+    let file = BufReader::new(File::open(archive_file)?);
+
+    match format {
+        ArchiveFormat::Tar(codec) => {
+            let mut archive = open_tar(file, codec, &DecompressionLimits::unlimited())?;
+            let mut entries = Vec::new();
+            for entry in archive.entries()? {
+                let entry = entry?;
+                let header = entry.header();
+                let is_dir = header.entry_type().is_dir();
+                entries.push(ArchiveEntry {
+                    path: entry.path()?.into_owned(),
+                    size: if is_dir { 0 } else { header.size()? },
+                    is_dir,
+                    mode: header.mode().ok(),
+                    mtime: header.mtime().ok(),
+                });
+            }
+            Ok(entries)
+        }
+        ArchiveFormat::Zip => {
+            let mut archive = ZipArchive::new(file)?;
+            let mut entries = Vec::with_capacity(archive.len());
+            for index in 0..archive.len() {
+                let file = archive.by_index_raw(index)?;
+                entries.push(ArchiveEntry {
+                    path: PathBuf::from(file.name().trim_end_matches('/')),
+                    size: file.size(),
+                    is_dir: file.is_dir(),
+                    mode: file.unix_mode().map(|mode| mode & 0o7777),
+                    mtime: file.last_modified().map(unix_seconds),
+                });
+            }
+            Ok(entries)
+        }
+    }
+}
+
+/// Extracts an archive into `destination`, creating it if needed.
+///
+/// Entries whose path is absolute or contains `..` are rejected, as are
+/// symbolic links, hard links and device files. Modes (without setuid,
+/// setgid and sticky bits) and modification times are restored. `limits`
+/// bounds the total size of the extracted files, also for uncompressed tar
+/// archives. On error, entries extracted so far are left in place.
+pub fn extract_archive(
+    archive_file: &Path,
+    format: ArchiveFormat,
+    destination: &Path,
+    limits: &DecompressionLimits,
+) -> io::Result<Vec<ArchiveEntry>> {
+    fs::create_dir_all(destination)?;
+    let file = BufReader::new(File::open(archive_file)?);
+
+    // Directory times are set last, since creating children changes them
+    let mut directories = Vec::new();
+    let entries = match format {
+        ArchiveFormat::Tar(codec) => extract_tar(
+            open_tar(file, codec, limits)?,
+            destination,
+            limits,
+            &mut directories,
+        )?,
+        ArchiveFormat::Zip => extract_zip(file, destination, limits, &mut directories)?,
+    };
+
+    for (path, mtime) in directories.into_iter().rev() {
+        filetime::set_file_mtime(path, FileTime::from_unix_time(mtime as i64, 0))?;
+    }
+
+    Ok(entries)
+}
+
+fn unsupported_entry(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "archive entry {} is a link or special file, which is not supported",
+            path.display()
+        ),
+    )
+}
+
+fn extract_tar<R: Read>(
+    mut archive: tar::Archive<R>,
+    destination: &Path,
+    limits: &DecompressionLimits,
+    directories: &mut Vec<(PathBuf, u64)>,
+) -> io::Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    let mut total_output = 0u64;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_pax_global_extensions() {
+            continue;
+        }
+
+        let path = safe_relative_path(&entry.path()?)?;
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            return Err(unsupported_entry(&path));
+        }
+
+        // The decoder only bounds compressed archives, and a plain tar is
+        // read as it is. Entries are read for exactly their recorded size.
+        let size = if entry_type.is_dir() {
+            0
+        } else {
+            entry.header().size()?
+        };
+        total_output = total_output.saturating_add(size);
+        if let Some(limit) = limits.max_output_size {
+            if total_output > limit {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    LimitExceeded::OutputSize { limit },
+                ));
+            }
+        }
+
+        let target = destination.join(&path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        entry.set_preserve_mtime(true);
+        entry.set_preserve_permissions(false);
+        entry.unpack(&target)?;
+
+        let header = entry.header();
+        let mtime = header.mtime().ok();
+        if entry_type.is_dir() {
+            directories.push((target, mtime.unwrap_or(0)));
+        }
+        entries.push(ArchiveEntry {
+            path,
+            size,
+            is_dir: entry_type.is_dir(),
+            mode: header.mode().ok().map(|mode| mode & 0o777),
+            mtime,
+        });
+    }
+
+    Ok(entries)
+}
+
+fn extract_zip<R: Read + Seek>(
+    reader: R,
+    destination: &Path,
+    limits: &DecompressionLimits,
+    directories: &mut Vec<(PathBuf, u64)>,
+) -> io::Result<Vec<ArchiveEntry>> {
+    let mut archive = ZipArchive::new(reader)?;
+    let mut entries = Vec::with_capacity(archive.len());
+    let mut total_output = 0u64;
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let name = file.name().to_string();
+        if name.contains('\\') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("archive entry {} contains a backslash", name),
+            ));
+        }
+        let path = safe_relative_path(Path::new(&name))?;
+        if file.is_symlink() {
+            return Err(unsupported_entry(&path));
+        }
+
+        let target = destination.join(&path);
+        let mtime = file.last_modified().map(unix_seconds);
+        let mode = file.unix_mode().map(|mode| mode & 0o777);
+
+        if file.is_dir() {
+            fs::create_dir_all(&target)?;
+            directories.push((target.clone(), mtime.unwrap_or(0)));
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            // Never trust the sizes recorded in the archive
+            let remaining = limits
+                .max_output_size
+                .map_or(u64::MAX, |limit| limit.saturating_sub(total_output));
+            let mut output = BufWriter::new(File::create(&target)?);
+            let written = io::copy(
+                &mut (&mut file).take(remaining.saturating_add(1)),
+                &mut output,
+            )?;
+            output.flush()?;
+            total_output += written;
+
+            if let Some(limit) = limits.max_output_size {
+                if total_output > limit {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        LimitExceeded::OutputSize { limit },
+                    ));
+                }
+            }
+            if let Some(limit) = limits.max_ratio {
+                let input = file.compressed_size();
+                if written > RATIO_GRACE_BYTES && written > input.saturating_mul(limit) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        LimitExceeded::Ratio {
+                            limit,
+                            input,
+                            output: written,
+                        },
+                    ));
+                }
+            }
+
+            #[cfg(unix)]
+            if let Some(mode) = mode {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&target, fs::Permissions::from_mode(mode))?;
+            }
+            if let Some(mtime) = mtime {
+                filetime::set_file_mtime(&target, FileTime::from_unix_time(mtime as i64, 0))?;
+            }
+        }
+
+        entries.push(ArchiveEntry {
+            path,
+            size: file.size(),
+            is_dir: file.is_dir(),
+            mode,
+            mtime,
+        });
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds `root/docs/a.txt` and `root/docs/nested/b.log` with known
+    /// contents and modification times.
+    fn create_tree(root: &str) -> io::Result<()> {
+        fs::create_dir_all(format!("{}/docs/nested", root))?;
+        fs::write(format!("{}/docs/a.txt", root), b"alpha ".repeat(100))?;
+        fs::write(format!("{}/docs/nested/b.log", root), b"beta\n".repeat(50))?;
+        filetime::set_file_mtime(
+            format!("{}/docs/a.txt", root),
+            FileTime::from_unix_time(1_600_000_000, 0),
+        )?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(
+                format!("{}/docs/nested/b.log", root),
+                fs::Permissions::from_mode(0o600),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn round_trip(root: &str, archive_name: &str) -> io::Result<()> {
+        create_tree(root)?;
+        let archive_path = PathBuf::from(format!("{}/{}", root, archive_name));
+        let format = ArchiveFormat::from_path(&archive_path).unwrap();
+        let out_dir = PathBuf::from(format!("{}/out", root));
+
+        let created = create_archive(&archive_path, format, None, &[format!("{}/docs", root)])?;
+        let listed = list_archive(&archive_path, format)?;
+        assert_eq!(
+            listed.iter().map(|e| &e.path).collect::<Vec<_>>(),
+            created.iter().map(|e| &e.path).collect::<Vec<_>>()
+        );
+
+        extract_archive(
+            &archive_path,
+            format,
+            &out_dir,
+            &DecompressionLimits::default(),
+        )?;
+        assert_eq!(fs::read(out_dir.join("docs/a.txt"))?, b"alpha ".repeat(100));
+        assert_eq!(
+            fs::read(out_dir.join("docs/nested/b.log"))?,
+            b"beta\n".repeat(50)
+        );
+
+        let mtime =
+            FileTime::from_last_modification_time(&fs::metadata(out_dir.join("docs/a.txt"))?);
+        assert_eq!(mtime.unix_seconds(), 1_600_000_000);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(out_dir.join("docs/nested/b.log"))?
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // Cleanup
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tar_gz_round_trip() -> io::Result<()> {
+        round_trip("test_archive_tar_gz", "batch.tar.gz")
+    }
+
+    #[test]
+    fn test_plain_tar_round_trip() -> io::Result<()> {
+        round_trip("test_archive_tar", "batch.tar")
+    }
+
+    #[test]
+    fn test_zip_round_trip() -> io::Result<()> {
+        round_trip("test_archive_zip", "batch.zip")
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert!(matches!(
+            ArchiveFormat::from_path(Path::new("a.tgz")),
+            Some(ArchiveFormat::Tar(Some(codec))) if codec.name() == "gzip"
+        ));
+        assert!(matches!(
+            ArchiveFormat::from_path(Path::new("a.zip")),
+            Some(ArchiveFormat::Zip)
+        ));
+        assert!(ArchiveFormat::from_path(Path::new("a.gz")).is_none());
+    }
+
+    #[test]
+    fn test_tar_rejects_path_traversal() -> io::Result<()> {
+        let root = "test_archive_slip_tar";
+        fs::create_dir_all(root)?;
+        let archive_path = PathBuf::from(format!("{}/evil.tar", root));
+
+        // tar::Builder refuses `..`, so write the name into the header directly
+        let mut header = tar::Header::new_gnu();
+        let name = b"../evil.txt";
+        header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name);
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        let mut builder = tar::Builder::new(File::create(&archive_path)?);
+        builder.append(&header, &b"evil"[..])?;
+        builder.into_inner()?;
+
+        let err = extract_archive(
+            &archive_path,
+            ArchiveFormat::Tar(None),
+            &PathBuf::from(format!("{}/out", root)),
+            &DecompressionLimits::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!Path::new(&format!("{}/evil.txt", root)).exists());
+
+        // Cleanup
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zip_rejects_path_traversal() -> io::Result<()> {
+        let root = "test_archive_slip_zip";
+        fs::create_dir_all(root)?;
+        let archive_path = PathBuf::from(format!("{}/evil.zip", root));
+
+        let mut zip = ZipWriter::new(File::create(&archive_path)?);
+        zip.start_file("../evil.txt", SimpleFileOptions::default())?;
+        zip.write_all(b"evil")?;
+        zip.finish()?;
+
+        let err = extract_archive(
+            &archive_path,
+            ArchiveFormat::Zip,
+            &PathBuf::from(format!("{}/out", root)),
+            &DecompressionLimits::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!Path::new(&format!("{}/evil.txt", root)).exists());
+
+        // Cleanup
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plain_tar_respects_output_limit() -> io::Result<()> {
+        let root = "test_archive_tar_limit";
+        fs::create_dir_all(root)?;
+        let archive_path = PathBuf::from(format!("{}/big.tar", root));
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4096);
+        header.set_mode(0o4755);
+        header.set_cksum();
+        let mut builder = tar::Builder::new(File::create(&archive_path)?);
+        builder.append_data(&mut header, "big.bin", &[0u8; 4096][..])?;
+        builder.into_inner()?;
+
+        let limits = DecompressionLimits {
+            max_output_size: Some(1024),
+            ..DecompressionLimits::default()
+        };
+        let out_dir = PathBuf::from(format!("{}/out", root));
+        let err = extract_archive(&archive_path, ArchiveFormat::Tar(None), &out_dir, &limits)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!out_dir.join("big.bin").exists());
+
+        // Setuid is dropped from the reported mode, as for zip entries
+        let entries = extract_archive(
+            &archive_path,
+            ArchiveFormat::Tar(None),
+            &out_dir,
+            &DecompressionLimits::default(),
+        )?;
+        assert_eq!(entries[0].mode, Some(0o755));
+
+        // Cleanup
+        fs::remove_dir_all(root)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_dos_datetime_round_trip() {
+        // Two-second resolution: odd seconds round down
+        assert_eq!(unix_seconds(dos_datetime(1_600_000_001)), 1_600_000_000);
+        assert_eq!(unix_seconds(dos_datetime(951_782_400)), 951_782_400); // 2000-02-29
+    }
+}
@@ -0,0 +1,468 @@
+use crate::codec::{codecs, Codec, Gzip};
+use crate::gzip_header::GzipHeader;
+use crate::progress::{compress_file_with_progress, Cancelled, ProgressReader};
+use crate::{CancellationToken, CompressionReport, Progress};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+/// Algorithm name that asks for automatic codec selection.
+pub const AUTO: &str = "auto";
+
+/// Magic bytes of formats that are already compressed.
+const KNOWN_FORMATS: &[(&str, usize, &[u8])] = &[
+    ("jpeg", 0, &[0xff, 0xd8, 0xff]),
+    ("png", 0, &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]),
+    ("gif", 0, b"GIF8"),
+    ("webp", 8, b"WEBP"),
+    ("mp4", 4, b"ftyp"),
+    ("matroska", 0, &[0x1a, 0x45, 0xdf, 0xa3]),
+    ("ogg", 0, b"OggS"),
+    ("flac", 0, b"fLaC"),
+    ("mp3", 0, b"ID3"),
+    ("zip", 0, &[b'P', b'K', 0x03, 0x04]),
+    ("gzip", 0, &[0x1f, 0x8b]),
+    ("zstd", 0, &[0x28, 0xb5, 0x2f, 0xfd]),
+    ("xz", 0, &[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+    ("bzip2", 0, b"BZh"),
+    ("lz4", 0, &[0x04, 0x22, 0x4d, 0x18]),
+    ("7z", 0, &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c]),
+    ("rar", 0, b"Rar!\x1a\x07"),
+];
+
+/// Returns the name of the compressed format `header` starts with, if any.
+pub fn sniff_format(header: &[u8]) -> Option<&'static str> {
+    KNOWN_FORMATS
+        .iter()
+        .find(|(_, offset, magic)| header.get(*offset..offset + magic.len()) == Some(*magic))
+        .map(|(name, _, _)| *name)
+}
+
+/// Settings for automatic codec selection.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoOptions {
+    /// Size of each sampled block.
+    pub sample_size: usize,
+    /// Number of blocks sampled, spread evenly across the input.
+    pub sample_count: usize,
+    /// Fraction of the input a codec must save for compression to be
+    /// worthwhile, e.g. `0.05` for 5%.
+    pub min_savings: f64,
+    /// Header written when gzip is chosen, e.g. to keep the original file
+    /// name. Gzip outputs get an empty header without one.
+    pub gzip_header: Option<GzipHeader>,
+}
+
+impl Default for AutoOptions {
+    fn default() -> Self {
+        Self {
+            sample_size: 64 * 1024,
+            sample_count: 4,
+            min_savings: 0.05,
+            gzip_header: None,
+        }
+    }
+}
+
+/// Why a file was kept as-is instead of being compressed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkipReason {
+    /// The input is empty.
+    Empty,
+    /// The input starts with the signature of a compressed format.
+    AlreadyCompressed { format: &'static str },
+    /// No codec saved enough space. `ratio` is compressed size over
+    /// original size, measured on samples or on the full output.
+    Incompressible { ratio: f64 },
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Empty => write!(f, "empty file"),
+            SkipReason::AlreadyCompressed { format } => {
+                write!(f, "already compressed ({})", format)
+            }
+            SkipReason::Incompressible { ratio } => {
+                write!(f, "incompressible (ratio {:.2})", ratio)
+            }
+        }
+    }
+}
+
+/// Outcome of [`choose_codec`].
+#[derive(Clone)]
+pub enum Decision {
+    /// Compress with `codec` at `level`. `estimated_ratio` is the
+    /// compressed to original size ratio seen on the samples.
+    Compress {
+        codec: &'static dyn Codec,
+        level: u32,
+        estimated_ratio: f64,
+    },
+    /// Keep the input as-is.
+    Store(SkipReason),
+}
+
+impl fmt::Debug for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Compress {
+                codec,
+                level,
+                estimated_ratio,
+            } => f
+                .debug_struct("Compress")
+                .field("codec", &codec.name())
+                .field("level", level)
+                .field("estimated_ratio", estimated_ratio)
+                .finish(),
+            Decision::Store(reason) => f.debug_tuple("Store").field(reason).finish(),
+        }
+    }
+}
+
+/// Reads up to `options.sample_count` blocks spread across the input.
+fn read_samples<R: Read + Seek>(reader: &mut R, options: &AutoOptions) -> io::Result<Vec<u8>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let sample_size = options.sample_size as u64;
+    let count = options.sample_count.max(1) as u64;
+    let mut samples = Vec::new();
+
+    if len <= sample_size * count {
+        reader.seek(SeekFrom::Start(0))?;
+        reader.read_to_end(&mut samples)?;
+    } else {
+        let span = len - sample_size;
+        for i in 0..count {
+            let offset = if count == 1 {
+                0
+            } else {
+                span * i / (count - 1)
+            };
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.take(sample_size).read_to_end(&mut samples)?;
+        }
+    }
+
+    reader.seek(SeekFrom::Start(0))?;
+    Ok(samples)
+}
+
+/// Decides whether and how to compress the data in `reader`.
+///
+/// Inputs with a known compressed signature are stored without sampling.
+/// Otherwise every available codec compresses the samples at its default
+/// level and the smallest result wins, provided it saves at least
+/// `options.min_savings`. The reader is rewound before returning.
+pub fn choose_codec<R: Read + Seek>(reader: &mut R, options: &AutoOptions) -> io::Result<Decision> {
+    let samples = read_samples(reader, options)?;
+    if samples.is_empty() {
+        return Ok(Decision::Store(SkipReason::Empty));
+    }
+    if let Some(format) = sniff_format(&samples) {
+        return Ok(Decision::Store(SkipReason::AlreadyCompressed { format }));
+    }
+
+    let mut best: Option<(&'static dyn Codec, f64)> = None;
+    for codec in codecs() {
+        let mut compressed = Vec::new();
+        crate::compress_stream_with(*codec, &samples[..], &mut compressed, codec.default_level())?;
+        let ratio = compressed.len() as f64 / samples.len() as f64;
+        if best.is_none_or(|(_, best_ratio)| ratio < best_ratio) {
+            best = Some((*codec, ratio));
+        }
+    }
+
+    // The gzip codec is always compiled in, so there is always a best codec
+    let (codec, ratio) = best.expect("at least one codec is available");
+    if ratio > 1.0 - options.min_savings {
+        return Ok(Decision::Store(SkipReason::Incompressible { ratio }));
+    }
+
+    Ok(Decision::Compress {
+        codec,
+        level: codec.default_level(),
+        estimated_ratio: ratio,
+    })
+}
+
+/// Result of [`compress_file_auto`].
+#[derive(Debug)]
+pub struct AutoOutcome {
+    /// What was done. A `Compress` decision whose full output turned out
+    /// not to save enough space is reported as `Store(Incompressible)`.
+    pub decision: Decision,
+    /// Path of the file that was written.
+    pub output_file: PathBuf,
+    pub report: CompressionReport,
+}
+
+/// Compresses `input_file` with the codec picked by [`choose_codec`], or
+/// copies it unchanged when compression would not pay off.
+///
+/// Compressed output is written to `{output_prefix}.{extension}` and
+/// stored copies to `output_prefix` itself. If the sample estimate was too
+/// optimistic and the real output misses `options.min_savings`, the
+/// compressed file is discarded and the input stored instead.
+pub fn compress_file_auto(
+    input_file: &str,
+    output_prefix: &str,
+    options: &AutoOptions,
+) -> io::Result<AutoOutcome> {
+    compress_file_auto_with_progress(
+        input_file,
+        output_prefix,
+        options,
+        &CancellationToken::new(),
+        |_| {},
+    )
+}
+
+/// Like [`compress_file_auto`], reporting progress through `on_progress`
+/// and stopping early once `cancel` is cancelled.
+///
+/// Progress covers the pass that writes the output. When a compressed
+/// output is discarded in favour of a stored copy, it starts over for the
+/// copy. On any error, cancellation included, no output is left behind.
+pub fn compress_file_auto_with_progress<F: FnMut(Progress)>(
+    input_file: &str,
+    output_prefix: &str,
+    options: &AutoOptions,
+    cancel: &CancellationToken,
+    mut on_progress: F,
+) -> io::Result<AutoOutcome> {
+    let decision = choose_codec(&mut File::open(input_file)?, options)?;
+    if cancel.is_cancelled() {
+        return Err(io::Error::other(Cancelled));
+    }
+
+    let reason = match decision {
+        Decision::Compress { codec, level, .. } => {
+            let output_file = format!("{}.{}", output_prefix, codec.extension());
+            let encoder: &dyn Codec = match &options.gzip_header {
+                Some(header) if codec.name() == Gzip.name() => header,
+                _ => codec,
+            };
+            let report = compress_file_with_progress(
+                encoder,
+                input_file,
+                &output_file,
+                level,
+                cancel,
+                &mut on_progress,
+            )?;
+            let ratio = report.bytes_written as f64 / report.bytes_read.max(1) as f64;
+            if ratio <= 1.0 - options.min_savings {
+                return Ok(AutoOutcome {
+                    decision,
+                    output_file: output_file.into(),
+                    report,
+                });
+            }
+            fs::remove_file(&output_file)?;
+            SkipReason::Incompressible { ratio }
+        }
+        Decision::Store(reason) => reason,
+    };
+
+    let copied =
+        store_with_progress(input_file, output_prefix, cancel, on_progress).inspect_err(|_| {
+            let _ = fs::remove_file(output_prefix);
+        })?;
+    Ok(AutoOutcome {
+        decision: Decision::Store(reason),
+        output_file: output_prefix.into(),
+        report: CompressionReport {
+            bytes_read: copied,
+            bytes_written: copied,
+        },
+    })
+}
+
+/// Copies `input_file` to `output_file` through a [`ProgressReader`].
+fn store_with_progress<F: FnMut(Progress)>(
+    input_file: &str,
+    output_file: &str,
+    cancel: &CancellationToken,
+    on_progress: F,
+) -> io::Result<u64> {
+    let input = File::open(input_file)?;
+    let total_bytes = input.metadata()?.len();
+    let mut input = BufReader::new(ProgressReader::new(
+        input,
+        Some(total_bytes),
+        cancel.clone(),
+        on_progress,
+    ));
+    let mut output = BufWriter::new(File::create(output_file)?);
+    let copied = io::copy(&mut input, &mut output)?;
+    output.flush()?;
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::path::Path;
+
+    /// Deterministic bytes that no codec can shrink.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sniff_format() {
+        assert_eq!(sniff_format(&[0xff, 0xd8, 0xff, 0xe0]), Some("jpeg"));
+        assert_eq!(sniff_format(b"\0\0\0\x18ftypmp42"), Some("mp4"));
+        assert_eq!(sniff_format(b"PK\x03\x04rest"), Some("zip"));
+        assert_eq!(sniff_format(b"plain text"), None);
+        assert_eq!(sniff_format(b""), None);
+    }
+
+    #[test]
+    fn test_known_formats_are_stored() -> io::Result<()> {
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0];
+        jpeg.extend(b"a".repeat(10_000));
+
+        let decision = choose_codec(&mut Cursor::new(jpeg), &AutoOptions::default())?;
+        assert!(matches!(
+            decision,
+            Decision::Store(SkipReason::AlreadyCompressed { format: "jpeg" })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_data_is_incompressible() -> io::Result<()> {
+        let decision = choose_codec(&mut Cursor::new(noise(500_000)), &AutoOptions::default())?;
+        assert!(matches!(
+            decision,
+            Decision::Store(SkipReason::Incompressible { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_text_is_compressed() -> io::Result<()> {
+        let text = b"timestamp=1 level=info message=\"request served\"\n".repeat(20_000);
+        let decision = choose_codec(&mut Cursor::new(text), &AutoOptions::default())?;
+        match decision {
+            Decision::Compress {
+                codec,
+                level,
+                estimated_ratio,
+            } => {
+                assert!(codec.levels().contains(&level));
+                assert!(estimated_ratio < 0.5);
+            }
+            other => panic!("expected compression, got {:?}", other),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress_file_auto_stores_incompressible_input() -> io::Result<()> {
+        let input_path = "test_auto_noise.bin";
+        let output_prefix = "test_auto_noise_out";
+        let data = noise(100_000);
+        fs::write(input_path, &data)?;
+
+        let outcome = compress_file_auto(input_path, output_prefix, &AutoOptions::default())?;
+        assert!(matches!(outcome.decision, Decision::Store(_)));
+        assert_eq!(outcome.output_file, PathBuf::from(output_prefix));
+        assert_eq!(fs::read(output_prefix)?, data);
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+        fs::remove_file(output_prefix)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress_file_auto_reports_progress_and_cancels() -> io::Result<()> {
+        let input_path = "test_auto_progress.txt";
+        let output_prefix = "test_auto_progress_out";
+        let text = b"level=info message=\"auto progress\"\n".repeat(10_000);
+        fs::write(input_path, &text)?;
+
+        let mut last = Progress::default();
+        let outcome = compress_file_auto_with_progress(
+            input_path,
+            output_prefix,
+            &AutoOptions::default(),
+            &CancellationToken::new(),
+            |progress| last = progress,
+        )?;
+        assert!(matches!(outcome.decision, Decision::Compress { .. }));
+        assert_eq!(last.bytes_processed, text.len() as u64);
+        assert_eq!(last.total_bytes, Some(text.len() as u64));
+        fs::remove_file(&outcome.output_file)?;
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let err = compress_file_auto_with_progress(
+            input_path,
+            output_prefix,
+            &AutoOptions::default(),
+            &cancel,
+            |_| {},
+        )
+        .unwrap_err();
+        assert!(crate::progress::is_cancelled(&err));
+        assert!(!Path::new(&outcome.output_file).exists());
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress_file_auto_writes_gzip_header() -> io::Result<()> {
+        let input_path = "test_auto_header.txt";
+        let output_prefix = "test_auto_header_out";
+        fs::write(
+            input_path,
+            b"level=info message=\"header\"\n".repeat(10_000),
+        )?;
+        let header = GzipHeader {
+            file_name: Some("report.log".to_string()),
+            mtime: Some(1_700_000_000),
+            comment: Some("task 7".to_string()),
+        };
+        let options = AutoOptions {
+            gzip_header: Some(header.clone()),
+            ..AutoOptions::default()
+        };
+
+        let outcome = compress_file_auto(input_path, output_prefix, &options)?;
+        let output_file = outcome.output_file.to_str().unwrap();
+        // Other codecs may win when more are compiled in
+        if let Decision::Compress { codec, .. } = outcome.decision {
+            if codec.name() == Gzip.name() {
+                assert_eq!(crate::gzip_header::read_gzip_header(output_file)?, header);
+            }
+        }
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+        fs::remove_file(output_file)?;
+
+        Ok(())
+    }
+}
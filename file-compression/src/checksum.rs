@@ -0,0 +1,222 @@
+use crate::codec::Codec;
+use crate::decompress::{DecompressionLimits, LimitedDecoder};
+use crate::{compress_stream_with, copy_stream, CompressionReport};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+/// Formats a digest as lowercase hex.
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Parses lowercase or uppercase hex, returning `None` if it is malformed.
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Reader wrapper that hashes everything read through it.
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Returns the hex SHA-256 of the bytes read so far.
+    pub fn finish(self) -> String {
+        to_hex(&self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Writer wrapper that hashes everything written through it.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Returns the hex SHA-256 of the bytes written so far.
+    pub fn finish(self) -> String {
+        to_hex(&self.hasher.finalize())
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Returns the hex SHA-256 of a file.
+pub fn sha256_file(path: &str) -> io::Result<String> {
+    let mut reader = HashingReader::new(BufReader::new(File::open(path)?));
+    copy_stream(&mut reader, &mut io::sink())?;
+    Ok(reader.finish())
+}
+
+/// A [`CompressionReport`] with SHA-256 digests of both sides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksumReport {
+    pub report: CompressionReport,
+    /// Hex SHA-256 of the uncompressed input.
+    pub original_sha256: String,
+    /// Hex SHA-256 of the compressed output.
+    pub compressed_sha256: String,
+}
+
+/// Like [`compress_stream_with`], hashing the input and output on the fly.
+pub fn compress_stream_checked<R: Read, W: Write>(
+    codec: &dyn Codec,
+    reader: R,
+    writer: W,
+    compression_level: u32,
+) -> io::Result<ChecksumReport> {
+    let mut input = HashingReader::new(reader);
+    let mut output = HashingWriter::new(writer);
+    let report = compress_stream_with(codec, &mut input, &mut output, compression_level)?;
+
+    Ok(ChecksumReport {
+        report,
+        original_sha256: input.finish(),
+        compressed_sha256: output.finish(),
+    })
+}
+
+/// Like [`crate::compress_file_with`], hashing the input and output.
+pub fn compress_file_checked(
+    codec: &dyn Codec,
+    input_file: &str,
+    output_file: &str,
+    compression_level: u32,
+) -> io::Result<ChecksumReport> {
+    let input = BufReader::new(File::open(input_file)?);
+    let output = BufWriter::new(File::create(output_file)?);
+    compress_stream_checked(codec, input, output, compression_level)
+}
+
+/// Decompresses `compressed_file` and checks that the result hashes to
+/// `original_sha256`, without writing the decompressed data anywhere.
+///
+/// `original_len`, when known, caps the decompressed size so a corrupted
+/// file cannot expand without bound. Fails with `InvalidData` on mismatch.
+pub fn verify_compressed_file(
+    codec: &dyn Codec,
+    compressed_file: &str,
+    original_sha256: &str,
+    original_len: Option<u64>,
+) -> io::Result<()> {
+    let limits = DecompressionLimits {
+        max_output_size: original_len,
+        max_ratio: None,
+    };
+    let input = BufReader::new(File::open(compressed_file)?);
+    let mut decoder = HashingReader::new(LimitedDecoder::with_codec(codec, input, limits)?);
+    let decompressed = copy_stream(&mut decoder, &mut io::sink())?;
+    let actual = decoder.finish();
+
+    if !actual.eq_ignore_ascii_case(original_sha256) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} decompresses to {} bytes with SHA-256 {}, expected {}",
+                compressed_file, decompressed, actual, original_sha256
+            ),
+        ));
+    }
+    if let Some(expected) = original_len.filter(|&len| len != decompressed) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} decompresses to {} bytes, expected {}",
+                compressed_file, decompressed, expected
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Gzip;
+    use std::fs;
+
+    #[test]
+    fn test_sha256_of_known_input() -> io::Result<()> {
+        let path = "test_checksum_known.txt";
+        fs::write(path, b"abc")?;
+
+        assert_eq!(
+            sha256_file(path)?,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        // Cleanup
+        fs::remove_file(path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checked_compression_and_verification() -> io::Result<()> {
+        let input_path = "test_checksum_input.txt";
+        let output_path = "test_checksum_output.gz";
+        fs::write(input_path, b"checksum me ".repeat(500))?;
+
+        let checked = compress_file_checked(&Gzip, input_path, output_path, 6)?;
+        assert_eq!(checked.original_sha256, sha256_file(input_path)?);
+        assert_eq!(checked.compressed_sha256, sha256_file(output_path)?);
+
+        verify_compressed_file(
+            &Gzip,
+            output_path,
+            &checked.original_sha256,
+            Some(checked.report.bytes_read),
+        )?;
+
+        // A different expected digest must be rejected
+        let err = verify_compressed_file(&Gzip, output_path, &"0".repeat(64), None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+        fs::remove_file(output_path)?;
+
+        Ok(())
+    }
+}
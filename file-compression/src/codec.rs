@@ -0,0 +1,491 @@
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+
+/// A compressing writer produced by a [`Codec`].
+pub trait Encoder: Write {
+    /// Writes any pending data and trailer, then flushes the underlying
+    /// writer. Dropping an encoder without calling this may leave a
+    /// truncated stream behind.
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+/// A compression algorithm that can be selected at runtime.
+pub trait Codec: Sync {
+    /// Name used to look the codec up, e.g. `"gzip"`.
+    fn name(&self) -> &'static str;
+
+    /// File extension for compressed output, without the leading dot.
+    fn extension(&self) -> &'static str;
+
+    /// Range of compression levels this codec accepts.
+    fn levels(&self) -> RangeInclusive<u32>;
+
+    /// Level used when the caller does not ask for one.
+    fn default_level(&self) -> u32;
+
+    /// Wraps `writer` in an encoder. `level` must lie within [`Codec::levels`].
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>>;
+
+    /// Wraps `reader` in a decoder producing the original bytes.
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>>;
+
+    /// Checks that `level` is valid for this codec.
+    fn validate_level(&self, level: u32) -> io::Result<()> {
+        let levels = self.levels();
+        if levels.contains(&level) {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} compression level must be between {} and {}, got {}",
+                    self.name(),
+                    levels.start(),
+                    levels.end(),
+                    level
+                ),
+            ))
+        }
+    }
+}
+
+/// Lets a borrowed codec be passed where an owned one is expected, such as
+/// `&'static dyn Codec` from the registry.
+impl<C: Codec + ?Sized> Codec for &C {
+    fn name(&self) -> &'static str {
+        (**self).name()
+    }
+
+    fn extension(&self) -> &'static str {
+        (**self).extension()
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        (**self).levels()
+    }
+
+    fn default_level(&self) -> u32 {
+        (**self).default_level()
+    }
+
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>> {
+        (**self).encoder(writer, level)
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        (**self).decoder(reader)
+    }
+
+    fn validate_level(&self, level: u32) -> io::Result<()> {
+        (**self).validate_level(level)
+    }
+}
+
+/// Codecs compiled into this build, gzip first.
+static CODECS: &[&dyn Codec] = &[
+    &Gzip,
+    #[cfg(feature = "zstd")]
+    &Zstd,
+    #[cfg(feature = "brotli")]
+    &Brotli,
+    #[cfg(feature = "xz")]
+    &Xz,
+    #[cfg(feature = "bzip2")]
+    &Bzip2,
+    #[cfg(feature = "lz4")]
+    &Lz4,
+];
+
+/// Returns every codec available in this build.
+pub fn codecs() -> &'static [&'static dyn Codec] {
+    CODECS
+}
+
+/// Looks a codec up by name, ignoring ASCII case.
+pub fn codec_by_name(name: &str) -> Option<&'static dyn Codec> {
+    CODECS
+        .iter()
+        .copied()
+        .find(|codec| codec.name().eq_ignore_ascii_case(name))
+}
+
+/// Looks a codec up by file extension, with or without the leading dot.
+pub fn codec_by_extension(extension: &str) -> Option<&'static dyn Codec> {
+    let extension = extension.trim_start_matches('.');
+    CODECS
+        .iter()
+        .copied()
+        .find(|codec| codec.extension().eq_ignore_ascii_case(extension))
+}
+
+/// Gzip through flate2. Always available.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Gzip;
+
+impl<W: Write> Encoder for GzEncoder<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        GzEncoder::finish(*self)?.flush()
+    }
+}
+
+impl Codec for Gzip {
+    fn name(&self) -> &'static str {
+        "gzip"
+    }
+
+    fn extension(&self) -> &'static str {
+        "gz"
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        0..=9
+    }
+
+    fn default_level(&self) -> u32 {
+        6
+    }
+
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>> {
+        self.validate_level(level)?;
+        Ok(Box::new(GzEncoder::new(writer, Compression::new(level))))
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    }
+}
+
+/// Zstandard through the `zstd` crate.
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Zstd;
+
+#[cfg(feature = "zstd")]
+impl<W: Write> Encoder for zstd::Encoder<'_, W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        zstd::Encoder::finish(*self)?.flush()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Codec for Zstd {
+    fn name(&self) -> &'static str {
+        "zstd"
+    }
+
+    fn extension(&self) -> &'static str {
+        "zst"
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        1..=22
+    }
+
+    fn default_level(&self) -> u32 {
+        3
+    }
+
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>> {
+        self.validate_level(level)?;
+        Ok(Box::new(zstd::Encoder::new(writer, level as i32)?))
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(zstd::Decoder::new(reader)?))
+    }
+}
+
+/// Brotli through the `brotli` crate.
+#[cfg(feature = "brotli")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Brotli;
+
+/// Brotli window size (log2), matching the reference encoder's default.
+#[cfg(feature = "brotli")]
+const BROTLI_LGWIN: u32 = 22;
+
+/// Writer that remembers the first error it returns.
+///
+/// `brotli::CompressorWriter::into_inner` drops errors from the final flush,
+/// so the encoder keeps them here to report them from `finish`.
+#[cfg(feature = "brotli")]
+struct ErrorTrap<W> {
+    inner: W,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "brotli")]
+impl<W: Write> Write for ErrorTrap<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf).inspect_err(|e| {
+            self.error
+                .get_or_insert_with(|| io::Error::new(e.kind(), e.to_string()));
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().inspect_err(|e| {
+            self.error
+                .get_or_insert_with(|| io::Error::new(e.kind(), e.to_string()));
+        })
+    }
+}
+
+#[cfg(feature = "brotli")]
+impl<W: Write> Encoder for brotli::CompressorWriter<ErrorTrap<W>> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        let mut trap = self.into_inner();
+        match trap.error.take() {
+            Some(e) => Err(e),
+            None => trap.inner.flush(),
+        }
+    }
+}
+
+#[cfg(feature = "brotli")]
+impl Codec for Brotli {
+    fn name(&self) -> &'static str {
+        "brotli"
+    }
+
+    fn extension(&self) -> &'static str {
+        "br"
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        0..=11
+    }
+
+    fn default_level(&self) -> u32 {
+        6
+    }
+
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>> {
+        self.validate_level(level)?;
+        let trap = ErrorTrap {
+            inner: writer,
+            error: None,
+        };
+        Ok(Box::new(brotli::CompressorWriter::new(
+            trap,
+            crate::BUFFER_SIZE,
+            level,
+            BROTLI_LGWIN,
+        )))
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(brotli::Decompressor::new(
+            reader,
+            crate::BUFFER_SIZE,
+        )))
+    }
+}
+
+/// XZ (LZMA2) through the `xz2` crate.
+#[cfg(feature = "xz")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Xz;
+
+#[cfg(feature = "xz")]
+impl<W: Write> Encoder for xz2::write::XzEncoder<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        xz2::write::XzEncoder::finish(*self)?.flush()
+    }
+}
+
+#[cfg(feature = "xz")]
+impl Codec for Xz {
+    fn name(&self) -> &'static str {
+        "xz"
+    }
+
+    fn extension(&self) -> &'static str {
+        "xz"
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        0..=9
+    }
+
+    fn default_level(&self) -> u32 {
+        6
+    }
+
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>> {
+        self.validate_level(level)?;
+        Ok(Box::new(xz2::write::XzEncoder::new(writer, level)))
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(xz2::read::XzDecoder::new_multi_decoder(reader)))
+    }
+}
+
+/// Bzip2 through the `bzip2` crate.
+#[cfg(feature = "bzip2")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bzip2;
+
+#[cfg(feature = "bzip2")]
+impl<W: Write> Encoder for bzip2::write::BzEncoder<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        bzip2::write::BzEncoder::finish(*self)?.flush()
+    }
+}
+
+#[cfg(feature = "bzip2")]
+impl Codec for Bzip2 {
+    fn name(&self) -> &'static str {
+        "bzip2"
+    }
+
+    fn extension(&self) -> &'static str {
+        "bz2"
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        1..=9
+    }
+
+    fn default_level(&self) -> u32 {
+        9
+    }
+
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>> {
+        self.validate_level(level)?;
+        Ok(Box::new(bzip2::write::BzEncoder::new(
+            writer,
+            bzip2::Compression::new(level),
+        )))
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(bzip2::read::MultiBzDecoder::new(reader)))
+    }
+}
+
+/// LZ4 frame format through `lz4_flex`. It has a single speed setting, so
+/// the only valid level is 1.
+#[cfg(feature = "lz4")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Lz4;
+
+#[cfg(feature = "lz4")]
+impl<W: Write> Encoder for lz4_flex::frame::FrameEncoder<W> {
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        lz4_flex::frame::FrameEncoder::finish(*self)?.flush()
+    }
+}
+
+#[cfg(feature = "lz4")]
+impl Codec for Lz4 {
+    fn name(&self) -> &'static str {
+        "lz4"
+    }
+
+    fn extension(&self) -> &'static str {
+        "lz4"
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        1..=1
+    }
+
+    fn default_level(&self) -> u32 {
+        1
+    }
+
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>> {
+        self.validate_level(level)?;
+        Ok(Box::new(lz4_flex::frame::FrameEncoder::new(writer)))
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(lz4_flex::frame::FrameDecoder::new(reader)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_stream_with, decompress_stream_with, DecompressionLimits};
+
+    #[test]
+    fn test_every_codec_round_trips_at_every_level() -> io::Result<()> {
+        let data = b"Codec round trip test data, repeated a few times. ".repeat(200);
+
+        for codec in codecs() {
+            for level in codec.levels() {
+                let mut compressed = Vec::new();
+                let report = compress_stream_with(*codec, &data[..], &mut compressed, level)?;
+                assert_eq!(report.bytes_read, data.len() as u64);
+                assert_eq!(report.bytes_written, compressed.len() as u64);
+
+                let mut decoded = Vec::new();
+                decompress_stream_with(
+                    *codec,
+                    &compressed[..],
+                    &mut decoded,
+                    &DecompressionLimits::default(),
+                )?;
+                assert_eq!(decoded, data, "{} level {}", codec.name(), level);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_registry_lookup() {
+        assert_eq!(codec_by_name("GZIP").map(|c| c.name()), Some("gzip"));
+        assert_eq!(codec_by_extension(".gz").map(|c| c.name()), Some("gzip"));
+        assert!(codec_by_name("unknown").is_none());
+
+        for codec in codecs() {
+            assert_eq!(codec_by_name(codec.name()).unwrap().name(), codec.name());
+            assert!(codec.levels().contains(&codec.default_level()));
+        }
+    }
+
+    #[test]
+    fn test_out_of_range_level_is_rejected() {
+        let err = compress_stream_with(&Gzip, &b"data"[..], Vec::new(), 10).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,331 @@
+use crate::codec::{Codec, Gzip};
+use crate::{copy_stream, CompressionReport, CountingWriter};
+use std::cell::Cell;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::rc::Rc;
+
+/// Output size below which the expansion ratio is not enforced, so that
+/// small, highly repetitive files are not rejected.
+pub(crate) const RATIO_GRACE_BYTES: u64 = 1024 * 1024;
+
+/// Limits applied while decompressing untrusted input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecompressionLimits {
+    /// Maximum number of decompressed bytes, `None` for no limit.
+    pub max_output_size: Option<u64>,
+    /// Maximum ratio of decompressed to compressed bytes, `None` for no limit.
+    pub max_ratio: Option<u64>,
+}
+
+impl DecompressionLimits {
+    /// Limits that never trigger. Only use this for trusted input.
+    pub fn unlimited() -> Self {
+        Self {
+            max_output_size: None,
+            max_ratio: None,
+        }
+    }
+}
+
+impl Default for DecompressionLimits {
+    fn default() -> Self {
+        Self {
+            max_output_size: Some(4 * 1024 * 1024 * 1024),
+            max_ratio: Some(1024),
+        }
+    }
+}
+
+/// Error returned (wrapped in an `io::Error` of kind `InvalidData`) when a
+/// decompression limit is hit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    /// The decompressed output grew past `max_output_size`.
+    OutputSize { limit: u64 },
+    /// The decompressed output grew past `max_ratio` times the input read.
+    Ratio { limit: u64, input: u64, output: u64 },
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::OutputSize { limit } => {
+                write!(f, "decompressed output exceeds the limit of {} bytes", limit)
+            }
+            LimitExceeded::Ratio {
+                limit,
+                input,
+                output,
+            } => write!(
+                f,
+                "decompressed output ({} bytes from {} compressed bytes) exceeds the maximum expansion ratio of {}",
+                output, input, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Reader wrapper that counts the bytes pulled through it. The count is
+/// shared because the reader itself ends up boxed inside a codec decoder.
+struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+/// Decoder that enforces [`DecompressionLimits`] as data is read.
+///
+/// Concatenated members are decoded as one stream where the codec supports
+/// it. Once a limit has been hit every further read fails.
+pub struct LimitedDecoder<'a> {
+    decoder: Box<dyn Read + 'a>,
+    compressed: Rc<Cell<u64>>,
+    limits: DecompressionLimits,
+    output: u64,
+    exceeded: Option<LimitExceeded>,
+}
+
+impl<'a> LimitedDecoder<'a> {
+    /// Decodes gzip data from `reader`.
+    pub fn new<R: Read + 'a>(reader: R, limits: DecompressionLimits) -> Self {
+        Self::with_codec(&Gzip, reader, limits).expect("gzip decoder construction is infallible")
+    }
+
+    /// Decodes data from `reader` compressed with `codec`.
+    pub fn with_codec<R: Read + 'a>(
+        codec: &dyn Codec,
+        reader: R,
+        limits: DecompressionLimits,
+    ) -> io::Result<Self> {
+        let compressed = Rc::new(Cell::new(0));
+        let decoder = codec.decoder(Box::new(CountingReader {
+            inner: reader,
+            count: Rc::clone(&compressed),
+        }))?;
+
+        Ok(Self {
+            decoder,
+            compressed,
+            limits,
+            output: 0,
+            exceeded: None,
+        })
+    }
+
+    /// Number of compressed bytes read from the underlying reader so far.
+    pub fn compressed_bytes(&self) -> u64 {
+        self.compressed.get()
+    }
+
+    /// Number of decompressed bytes produced so far.
+    pub fn decompressed_bytes(&self) -> u64 {
+        self.output
+    }
+
+    fn check_limits(&self) -> Option<LimitExceeded> {
+        if let Some(limit) = self.limits.max_output_size {
+            if self.output > limit {
+                return Some(LimitExceeded::OutputSize { limit });
+            }
+        }
+
+        if let Some(limit) = self.limits.max_ratio {
+            let input = self.compressed_bytes();
+            if self.output > RATIO_GRACE_BYTES && self.output > input.saturating_mul(limit) {
+                return Some(LimitExceeded::Ratio {
+                    limit,
+                    input,
+                    output: self.output,
+                });
+            }
+        }
+
+        None
+    }
+}
+
+impl Read for LimitedDecoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(exceeded) = &self.exceeded {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, exceeded.clone()));
+        }
+
+        let n = self.decoder.read(buf)?;
+        self.output += n as u64;
+
+        if let Some(exceeded) = self.check_limits() {
+            self.exceeded = Some(exceeded.clone());
+            return Err(io::Error::new(io::ErrorKind::InvalidData, exceeded));
+        }
+
+        Ok(n)
+    }
+}
+
+/// Decompresses a gzip stream from `reader` into `writer`, enforcing `limits`.
+///
+/// In the returned report `bytes_read` counts compressed input and
+/// `bytes_written` counts decompressed output.
+pub fn decompress_stream<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    limits: &DecompressionLimits,
+) -> io::Result<CompressionReport> {
+    decompress_stream_with(&Gzip, reader, writer, limits)
+}
+
+/// Like [`decompress_stream`], for data compressed with `codec`.
+pub fn decompress_stream_with<R: Read, W: Write>(
+    codec: &dyn Codec,
+    reader: R,
+    writer: W,
+    limits: &DecompressionLimits,
+) -> io::Result<CompressionReport> {
+    let mut decoder = LimitedDecoder::with_codec(codec, reader, *limits)?;
+    let mut output = CountingWriter::new(writer);
+    copy_stream(&mut decoder, &mut output)?;
+    output.flush()?;
+
+    Ok(CompressionReport {
+        bytes_read: decoder.compressed_bytes(),
+        bytes_written: output.count,
+    })
+}
+
+/// Decompresses a gzip file, enforcing `limits`.
+///
+/// The partially written output file is removed if decompression fails.
+pub fn decompress_file(
+    input_file: &str,
+    output_file: &str,
+    limits: &DecompressionLimits,
+) -> io::Result<CompressionReport> {
+    decompress_file_with(&Gzip, input_file, output_file, limits)
+}
+
+/// Like [`decompress_file`], for a file compressed with `codec`.
+pub fn decompress_file_with(
+    codec: &dyn Codec,
+    input_file: &str,
+    output_file: &str,
+    limits: &DecompressionLimits,
+) -> io::Result<CompressionReport> {
+    let input = BufReader::new(File::open(input_file)?);
+    let output = BufWriter::new(File::create(output_file)?);
+
+    decompress_stream_with(codec, input, output, limits).inspect_err(|_| {
+        let _ = fs::remove_file(output_file);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress_stream;
+    use std::path::Path;
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        compress_stream(data, &mut compressed, 9).unwrap();
+        compressed
+    }
+
+    fn limit_error(err: &io::Error) -> Option<&LimitExceeded> {
+        err.get_ref().and_then(|inner| inner.downcast_ref())
+    }
+
+    #[test]
+    fn test_decompress_file_round_trip() -> io::Result<()> {
+        let input_path = "test_decompress_input.gz";
+        let output_path = "test_decompress_output.txt";
+        let data = b"This is a test file for decompression.\n".repeat(100);
+
+        fs::write(input_path, gzip(&data))?;
+        let report = decompress_file(input_path, output_path, &DecompressionLimits::default())?;
+
+        assert_eq!(fs::read(output_path)?, data);
+        assert_eq!(report.bytes_written, data.len() as u64);
+        assert_eq!(report.bytes_read, fs::metadata(input_path)?.len());
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+        fs::remove_file(output_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_size_limit_is_enforced() {
+        let compressed = gzip(&[0u8; 10_000]);
+        let limits = DecompressionLimits {
+            max_output_size: Some(1_000),
+            max_ratio: None,
+        };
+
+        let err = decompress_stream(&compressed[..], io::sink(), &limits).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            limit_error(&err),
+            Some(&LimitExceeded::OutputSize { limit: 1_000 })
+        );
+    }
+
+    #[test]
+    fn test_ratio_limit_rejects_zip_bomb() {
+        // 16 MiB of zeros compresses to a few KiB
+        let compressed = gzip(&vec![0u8; 16 * 1024 * 1024]);
+        let limits = DecompressionLimits {
+            max_output_size: None,
+            max_ratio: Some(100),
+        };
+
+        let err = decompress_stream(&compressed[..], io::sink(), &limits).unwrap_err();
+        assert!(matches!(
+            limit_error(&err),
+            Some(LimitExceeded::Ratio { limit: 100, .. })
+        ));
+    }
+
+    #[test]
+    fn test_failed_decompress_file_removes_output() -> io::Result<()> {
+        let input_path = "test_decompress_bomb.gz";
+        let output_path = "test_decompress_bomb.txt";
+        let limits = DecompressionLimits {
+            max_output_size: Some(10),
+            max_ratio: None,
+        };
+
+        fs::write(input_path, gzip(&[1u8; 1_000]))?;
+        assert!(decompress_file(input_path, output_path, &limits).is_err());
+        assert!(!Path::new(output_path).exists());
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_limited_decoder_reads_concatenated_members() -> io::Result<()> {
+        let mut compressed = gzip(b"first ");
+        compressed.extend(gzip(b"second"));
+
+        let mut decoded = String::new();
+        LimitedDecoder::new(&compressed[..], DecompressionLimits::default())
+            .read_to_string(&mut decoded)?;
+        assert_eq!(decoded, "first second");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use crate::codec::{Codec, Encoder};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// Algorithm name for zstd with the latest trained dictionary.
+pub const ZSTD_DICT: &str = "zstd-dict";
+
+/// Default maximum dictionary size, matching the `zstd --train` default.
+pub const DEFAULT_DICTIONARY_SIZE: usize = 110 * 1024;
+
+/// A trained zstd dictionary and the version it is stored under.
+///
+/// `Dictionary` is a [`Codec`], so it works with every `*_with` function.
+/// Output can only be decompressed with the same dictionary version.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Dictionary {
+    version: u32,
+    data: Vec<u8>,
+}
+
+impl fmt::Debug for Dictionary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dictionary")
+            .field("version", &self.version)
+            .field("size", &self.data.len())
+            .finish()
+    }
+}
+
+impl Dictionary {
+    pub fn new(version: u32, data: Vec<u8>) -> Self {
+        Self { version, data }
+    }
+
+    /// Trains a dictionary of at most `max_size` bytes from `samples`.
+    ///
+    /// zstd needs a reasonable number of samples, typically a few hundred,
+    /// and fails with an error when given too few.
+    pub fn train<S: AsRef<[u8]>>(version: u32, samples: &[S], max_size: usize) -> io::Result<Self> {
+        Ok(Self::new(
+            version,
+            zstd::dict::from_samples(samples, max_size)?,
+        ))
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Codec for Dictionary {
+    fn name(&self) -> &'static str {
+        ZSTD_DICT
+    }
+
+    fn extension(&self) -> &'static str {
+        "zst"
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        1..=22
+    }
+
+    fn default_level(&self) -> u32 {
+        3
+    }
+
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>> {
+        self.validate_level(level)?;
+        Ok(Box::new(zstd::Encoder::with_dictionary(
+            writer,
+            level as i32,
+            &self.data,
+        )?))
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(zstd::Decoder::with_dictionary(
+            BufReader::new(reader),
+            &self.data,
+        )?))
+    }
+}
+
+/// Directory of dictionaries, one `v{version}.dict` file per version.
+///
+/// Versions start at 1 and are never reused, so outputs that recorded a
+/// version stay decodable after retraining.
+#[derive(Debug, Clone)]
+pub struct DictionaryStore {
+    dir: PathBuf,
+}
+
+impl DictionaryStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, version: u32) -> PathBuf {
+        self.dir.join(format!("v{}.dict", version))
+    }
+
+    /// Returns the stored versions in ascending order. A missing directory
+    /// holds no versions.
+    pub fn versions(&self) -> io::Result<Vec<u32>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut versions = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+            let version = name
+                .to_str()
+                .and_then(|name| name.strip_prefix('v'))
+                .and_then(|name| name.strip_suffix(".dict"))
+                .and_then(|version| version.parse().ok());
+            if let Some(version) = version {
+                versions.push(version);
+            }
+        }
+        versions.sort_unstable();
+        Ok(versions)
+    }
+
+    /// Loads a stored version. Fails with `NotFound` if it does not exist.
+    pub fn load(&self, version: u32) -> io::Result<Dictionary> {
+        let data = fs::read(self.path(version)).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("dictionary version {} is unavailable: {}", version, e),
+            )
+        })?;
+        Ok(Dictionary::new(version, data))
+    }
+
+    /// Loads the highest stored version, if any.
+    pub fn latest(&self) -> io::Result<Option<Dictionary>> {
+        match self.versions()?.last() {
+            Some(&version) => self.load(version).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores `data` under the next free version.
+    pub fn save(&self, data: Vec<u8>) -> io::Result<Dictionary> {
+        fs::create_dir_all(&self.dir)?;
+        let version = self.versions()?.last().map_or(1, |latest| latest + 1);
+
+        // create_new so two concurrent saves cannot overwrite one another
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.path(version))?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+
+        Ok(Dictionary::new(version, data))
+    }
+
+    /// Trains a dictionary from the contents of `sample_files` and stores it
+    /// under the next free version.
+    pub fn train<P: AsRef<Path>>(
+        &self,
+        sample_files: &[P],
+        max_size: usize,
+    ) -> io::Result<Dictionary> {
+        let mut samples = Vec::with_capacity(sample_files.len());
+        for path in sample_files {
+            let mut sample = Vec::new();
+            File::open(path)?.read_to_end(&mut sample)?;
+            samples.push(sample);
+        }
+
+        let trained = Dictionary::train(0, &samples, max_size)?;
+        self.save(trained.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_stream_with, decompress_stream_with, DecompressionLimits};
+
+    /// Small JSON documents that share most of their structure.
+    fn json_samples(count: usize) -> Vec<Vec<u8>> {
+        (0..count)
+            .map(|i| {
+                format!(
+                    r#"{{"id":{},"user":"user-{}","event":"{}","status":"ok","latency_ms":{}}}"#,
+                    i,
+                    i % 37,
+                    ["login", "logout", "upload", "download"][i % 4],
+                    (i * 13) % 500
+                )
+                .into_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dictionary_round_trip_beats_plain_zstd() -> io::Result<()> {
+        let samples = json_samples(1000);
+        let dictionary = Dictionary::train(1, &samples, 4096)?;
+        let input =
+            br#"{"id":5000,"user":"user-3","event":"upload","status":"ok","latency_ms":42}"#;
+
+        let mut with_dictionary = Vec::new();
+        compress_stream_with(&dictionary, &input[..], &mut with_dictionary, 3)?;
+        let mut plain = Vec::new();
+        compress_stream_with(&crate::codec::Zstd, &input[..], &mut plain, 3)?;
+        assert!(with_dictionary.len() < plain.len());
+
+        let mut decoded = Vec::new();
+        decompress_stream_with(
+            &dictionary,
+            &with_dictionary[..],
+            &mut decoded,
+            &DecompressionLimits::default(),
+        )?;
+        assert_eq!(decoded, input);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_versions_dictionaries() -> io::Result<()> {
+        let dir = "test_dictionary_store";
+        let store = DictionaryStore::new(dir);
+        assert!(store.latest()?.is_none());
+
+        let sample_dir = Path::new(dir).join("samples");
+        fs::create_dir_all(&sample_dir)?;
+        let mut sample_files = Vec::new();
+        for (i, sample) in json_samples(500).iter().enumerate() {
+            let path = sample_dir.join(format!("{}.json", i));
+            fs::write(&path, sample)?;
+            sample_files.push(path);
+        }
+
+        let first = store.train(&sample_files, 4096)?;
+        let second = store.save(b"raw content dictionary".to_vec())?;
+        assert_eq!((first.version(), second.version()), (1, 2));
+        assert_eq!(store.versions()?, vec![1, 2]);
+        assert_eq!(store.latest()?, Some(second));
+        assert_eq!(store.load(1)?, first);
+        assert_eq!(store.load(3).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        // Cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+}
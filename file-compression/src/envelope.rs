@@ -0,0 +1,484 @@
+use crate::auto::sniff_format;
+use crate::checksum::{compress_stream_checked, from_hex, to_hex, HashingWriter};
+use crate::codec::{codec_by_name, Codec};
+use crate::decompress::{DecompressionLimits, LimitedDecoder};
+use crate::{copy_stream, CompressionReport, CountingWriter};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+
+/// Bytes every envelope starts with.
+pub const ENVELOPE_MAGIC: [u8; 4] = *b"FCEV";
+
+/// Envelope layout version written by this library. Later versions may
+/// only append header fields, so readers accept them too.
+const ENVELOPE_VERSION: u8 = 1;
+
+/// Upper bound on the header size, so a corrupt length cannot trigger a
+/// huge allocation.
+const MAX_HEADER_SIZE: usize = 1024 * 1024;
+
+/// Size of the magic, version and header length.
+const PREAMBLE_SIZE: usize = 9;
+
+/// Position of the original length and SHA-256 within an envelope. They are
+/// patched in once the input has been read.
+const PATCH_RANGE: std::ops::Range<usize> = PREAMBLE_SIZE + 1..PREAMBLE_SIZE + 1 + 8 + 32;
+
+/// Header of the self-describing envelope.
+///
+/// On disk: the magic, a version byte and a little-endian `u32` length of
+/// the fields that follow, then the level (`u8`), original length (`u64`),
+/// original SHA-256 (32 bytes), the codec name (`u8` length and bytes) and
+/// the metadata (`u16` count, then `u16`-prefixed keys and `u32`-prefixed
+/// values). Readers accept any version from 1 up and skip fields past the
+/// ones they know, so later versions can append fields. The compressed data
+/// follows the header.
+///
+/// Only codecs that [`codec_by_name`] can look up are allowed, since the
+/// reader has nothing else to go on; a dictionary or external codec is
+/// rejected when writing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvelopeHeader {
+    /// Name of the codec, as accepted by [`codec_by_name`].
+    pub codec: String,
+    pub level: u32,
+    pub original_len: u64,
+    /// Hex SHA-256 of the original data.
+    pub original_sha256: String,
+    /// Free-form key/value pairs supplied by the writer.
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl EnvelopeHeader {
+    /// Serializes the header, magic included.
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let too_large = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("envelope {} is too large", what),
+            )
+        };
+        let sha256 = from_hex(&self.original_sha256)
+            .filter(|digest| digest.len() == 32)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "original SHA-256 must be 64 hex digits",
+                )
+            })?;
+
+        let mut fields = Vec::new();
+        fields.push(u8::try_from(self.level).map_err(|_| too_large("level"))?);
+        fields.extend_from_slice(&self.original_len.to_le_bytes());
+        fields.extend_from_slice(&sha256);
+        fields.push(u8::try_from(self.codec.len()).map_err(|_| too_large("codec name"))?);
+        fields.extend_from_slice(self.codec.as_bytes());
+        let count = u16::try_from(self.metadata.len()).map_err(|_| too_large("metadata"))?;
+        fields.extend_from_slice(&count.to_le_bytes());
+        for (key, value) in &self.metadata {
+            let key_len = u16::try_from(key.len()).map_err(|_| too_large("metadata key"))?;
+            let value_len = u32::try_from(value.len()).map_err(|_| too_large("metadata value"))?;
+            fields.extend_from_slice(&key_len.to_le_bytes());
+            fields.extend_from_slice(key.as_bytes());
+            fields.extend_from_slice(&value_len.to_le_bytes());
+            fields.extend_from_slice(value.as_bytes());
+        }
+        if fields.len() > MAX_HEADER_SIZE {
+            return Err(too_large("header"));
+        }
+
+        let mut bytes = Vec::with_capacity(PREAMBLE_SIZE + fields.len());
+        bytes.extend_from_slice(&ENVELOPE_MAGIC);
+        bytes.push(ENVELOPE_VERSION);
+        bytes.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&fields);
+        Ok(bytes)
+    }
+
+    /// Parses the header fields, after the magic, version and length.
+    fn from_fields(fields: &[u8]) -> io::Result<Self> {
+        let mut fields = Cursor::new(fields);
+        let level = read_array::<1>(&mut fields)?[0] as u32;
+        let original_len = u64::from_le_bytes(read_array(&mut fields)?);
+        let original_sha256 = to_hex(&read_array::<32>(&mut fields)?);
+        let codec_len = read_array::<1>(&mut fields)?[0] as usize;
+        let codec = read_string(&mut fields, codec_len)?;
+
+        let count = u16::from_le_bytes(read_array(&mut fields)?);
+        let mut metadata = BTreeMap::new();
+        for _ in 0..count {
+            let key_len = u16::from_le_bytes(read_array(&mut fields)?) as usize;
+            let key = read_string(&mut fields, key_len)?;
+            let value_len = u32::from_le_bytes(read_array(&mut fields)?) as usize;
+            let value = read_string(&mut fields, value_len)?;
+            metadata.insert(key, value);
+        }
+
+        Ok(Self {
+            codec,
+            level,
+            original_len,
+            original_sha256,
+            metadata,
+        })
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut Cursor<&[u8]>) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes).map_err(|_| truncated())?;
+    Ok(bytes)
+}
+
+fn read_string(reader: &mut Cursor<&[u8]>, len: usize) -> io::Result<String> {
+    // Checked up front so a bogus length cannot trigger a huge allocation
+    let remaining = reader.get_ref().len() - reader.position() as usize;
+    if len > remaining {
+        return Err(truncated());
+    }
+    let mut bytes = vec![0u8; len];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "envelope header is truncated")
+}
+
+/// Returns true if `prefix` starts with [`ENVELOPE_MAGIC`].
+pub fn is_envelope(prefix: &[u8]) -> bool {
+    prefix.starts_with(&ENVELOPE_MAGIC)
+}
+
+/// Reads an envelope header, leaving `reader` at the compressed data.
+pub fn read_envelope_header<R: Read>(reader: R) -> io::Result<EnvelopeHeader> {
+    read_header_with_len(reader).map(|(header, _)| header)
+}
+
+/// Like [`read_envelope_header`], also returning how many bytes the header
+/// took. Headers of later versions can be longer than the fields this
+/// reader knows.
+fn read_header_with_len<R: Read>(mut reader: R) -> io::Result<(EnvelopeHeader, usize)> {
+    let mut preamble = [0u8; PREAMBLE_SIZE];
+    reader.read_exact(&mut preamble)?;
+    if !is_envelope(&preamble) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "input does not start with an envelope header",
+        ));
+    }
+    if preamble[4] == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported envelope version {}", preamble[4]),
+        ));
+    }
+
+    let len = u32::from_le_bytes(preamble[5..9].try_into().unwrap()) as usize;
+    if len > MAX_HEADER_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("envelope header of {} bytes exceeds the limit", len),
+        ));
+    }
+    let mut fields = vec![0u8; len];
+    reader.read_exact(&mut fields).map_err(|_| truncated())?;
+    Ok((EnvelopeHeader::from_fields(&fields)?, PREAMBLE_SIZE + len))
+}
+
+/// Compresses `reader` with `codec` into `writer`, behind an envelope header
+/// recording the codec, level, original length and SHA-256, and `metadata`.
+///
+/// The writer must be seekable because the length and digest are only
+/// known once the input has been read; they are filled in at the end.
+pub fn compress_stream_enveloped<R: Read, W: Write + Seek>(
+    codec: &dyn Codec,
+    reader: R,
+    mut writer: W,
+    compression_level: u32,
+    metadata: &BTreeMap<String, String>,
+) -> io::Result<(CompressionReport, EnvelopeHeader)> {
+    codec.validate_level(compression_level)?;
+    if codec_by_name(codec.name()).is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "codec {} cannot be looked up by name, so its envelope could not be read",
+                codec.name()
+            ),
+        ));
+    }
+    let mut header = EnvelopeHeader {
+        codec: codec.name().to_string(),
+        level: compression_level,
+        original_len: 0,
+        original_sha256: "0".repeat(64),
+        metadata: metadata.clone(),
+    };
+
+    let start = writer.stream_position()?;
+    let placeholder = header.to_bytes()?;
+    writer.write_all(&placeholder)?;
+    let checked = compress_stream_checked(codec, reader, &mut writer, compression_level)?;
+    let end = writer.stream_position()?;
+
+    header.original_len = checked.report.bytes_read;
+    header.original_sha256 = checked.original_sha256;
+    let patched = header.to_bytes()?;
+    writer.seek(SeekFrom::Start(start + PATCH_RANGE.start as u64))?;
+    writer.write_all(&patched[PATCH_RANGE])?;
+    writer.seek(SeekFrom::Start(end))?;
+    writer.flush()?;
+
+    Ok((
+        CompressionReport {
+            bytes_read: checked.report.bytes_read,
+            bytes_written: end - start,
+        },
+        header,
+    ))
+}
+
+/// Like [`compress_stream_enveloped`], for files.
+pub fn compress_file_enveloped(
+    codec: &dyn Codec,
+    input_file: &str,
+    output_file: &str,
+    compression_level: u32,
+    metadata: &BTreeMap<String, String>,
+) -> io::Result<(CompressionReport, EnvelopeHeader)> {
+    let input = BufReader::new(File::open(input_file)?);
+    let output = BufWriter::new(File::create(output_file)?);
+    compress_stream_enveloped(codec, input, output, compression_level, metadata)
+}
+
+/// Result of [`decompress_stream_detect`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detected {
+    /// Name of the codec the data was decoded with.
+    pub codec: String,
+    /// The envelope header, if the input had one.
+    pub envelope: Option<EnvelopeHeader>,
+    pub report: CompressionReport,
+}
+
+/// Decompresses `reader` without being told the format.
+///
+/// Enveloped input is decoded with the codec named in its header, and the
+/// output is checked against the recorded length and SHA-256. Other input
+/// is identified by its magic bytes, which works for every codec except
+/// brotli. Fails with `InvalidData` if the format is not recognized or the
+/// output does not match the envelope.
+pub fn decompress_stream_detect<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    limits: &DecompressionLimits,
+) -> io::Result<Detected> {
+    // Longest signature among the formats a codec can decode
+    let mut prefix = Vec::new();
+    (&mut reader).take(16).read_to_end(&mut prefix)?;
+
+    if is_envelope(&prefix) {
+        let mut reader = Cursor::new(prefix).chain(reader);
+        let (header, header_len) = read_header_with_len(&mut reader)?;
+        let codec = lookup_codec(&header.codec)?;
+        let limits = DecompressionLimits {
+            max_output_size: Some(
+                limits
+                    .max_output_size
+                    .map_or(header.original_len, |max| max.min(header.original_len)),
+            ),
+            ..*limits
+        };
+
+        let mut decoder = LimitedDecoder::with_codec(codec, reader, limits)?;
+        let mut output = CountingWriter::new(writer);
+        let mut hashing = HashingWriter::new(&mut output);
+        copy_stream(&mut decoder, &mut hashing)?;
+        hashing.flush()?;
+        let sha256 = hashing.finish();
+
+        if output.count != header.original_len || sha256 != header.original_sha256 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "decoded {} bytes with SHA-256 {}, envelope records {} bytes with {}",
+                    output.count, sha256, header.original_len, header.original_sha256
+                ),
+            ));
+        }
+
+        return Ok(Detected {
+            codec: header.codec.clone(),
+            report: CompressionReport {
+                bytes_read: header_len as u64 + decoder.compressed_bytes(),
+                bytes_written: output.count,
+            },
+            envelope: Some(header),
+        });
+    }
+
+    let codec = sniff_format(&prefix)
+        .and_then(codec_by_name)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "input is neither an envelope nor in a format this build can decode",
+            )
+        })?;
+    let report =
+        crate::decompress_stream_with(codec, Cursor::new(prefix).chain(reader), writer, limits)?;
+
+    Ok(Detected {
+        codec: codec.name().to_string(),
+        envelope: None,
+        report,
+    })
+}
+
+fn lookup_codec(name: &str) -> io::Result<&'static dyn Codec> {
+    codec_by_name(name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("codec {} is not available in this build", name),
+        )
+    })
+}
+
+/// Like [`decompress_stream_detect`], for files. The partially written
+/// output file is removed if decompression fails.
+pub fn decompress_file_detect(
+    input_file: &str,
+    output_file: &str,
+    limits: &DecompressionLimits,
+) -> io::Result<Detected> {
+    let input = BufReader::new(File::open(input_file)?);
+    let output = BufWriter::new(File::create(output_file)?);
+
+    decompress_stream_detect(input, output, limits).inspect_err(|_| {
+        let _ = fs::remove_file(output_file);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{codecs, Gzip};
+
+    fn metadata() -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("file_name".to_string(), "events.log".to_string()),
+            ("task_id".to_string(), "7".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_envelope_round_trip_for_every_codec() -> io::Result<()> {
+        let data = b"enveloped data ".repeat(1000);
+
+        for codec in codecs() {
+            let mut enveloped = Cursor::new(Vec::new());
+            let (report, header) =
+                compress_stream_enveloped(*codec, &data[..], &mut enveloped, 1, &metadata())?;
+            let enveloped = enveloped.into_inner();
+            assert_eq!(report.bytes_written, enveloped.len() as u64);
+            assert_eq!(header.original_len, data.len() as u64);
+
+            // The patched header reads back exactly
+            assert_eq!(read_envelope_header(&enveloped[..])?, header);
+
+            let mut decoded = Vec::new();
+            let detected =
+                decompress_stream_detect(&enveloped[..], &mut decoded, &Default::default())?;
+            assert_eq!(decoded, data, "codec {}", codec.name());
+            assert_eq!(detected.codec, codec.name());
+            assert_eq!(detected.envelope.unwrap().metadata, metadata());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_detects_plain_gzip() -> io::Result<()> {
+        let mut compressed = Vec::new();
+        crate::compress_stream(&b"plain gzip"[..], &mut compressed, 6)?;
+
+        let mut decoded = Vec::new();
+        let detected =
+            decompress_stream_detect(&compressed[..], &mut decoded, &Default::default())?;
+        assert_eq!(decoded, b"plain gzip");
+        assert_eq!(detected.codec, "gzip");
+        assert!(detected.envelope.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_digest_mismatch_is_rejected() -> io::Result<()> {
+        let mut enveloped = Cursor::new(Vec::new());
+        compress_stream_enveloped(&Gzip, &b"x".repeat(100)[..], &mut enveloped, 6, &metadata())?;
+        let mut enveloped = enveloped.into_inner();
+
+        // First byte of the recorded SHA-256
+        enveloped[PATCH_RANGE.start + 8] ^= 1;
+
+        let err =
+            decompress_stream_detect(&enveloped[..], io::sink(), &Default::default()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_later_version_with_extra_fields_is_read() -> io::Result<()> {
+        let mut enveloped = Cursor::new(Vec::new());
+        let (_, header) = compress_stream_enveloped(
+            &Gzip,
+            &b"y".repeat(100)[..],
+            &mut enveloped,
+            6,
+            &metadata(),
+        )?;
+        let enveloped = enveloped.into_inner();
+
+        // A version 2 header with a field this reader does not know
+        let header_len = header.to_bytes()?.len();
+        let mut newer = enveloped[..header_len].to_vec();
+        newer[4] = 2;
+        newer.extend_from_slice(b"extra");
+        let fields_len = (header_len - PREAMBLE_SIZE + 5) as u32;
+        newer[5..9].copy_from_slice(&fields_len.to_le_bytes());
+        newer.extend_from_slice(&enveloped[header_len..]);
+
+        let mut decoded = Vec::new();
+        let detected = decompress_stream_detect(&newer[..], &mut decoded, &Default::default())?;
+        assert_eq!(decoded, b"y".repeat(100));
+        assert_eq!(detected.report.bytes_read, newer.len() as u64);
+        assert_eq!(detected.envelope.unwrap(), header);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_dictionary_codec_is_rejected() {
+        let dictionary = crate::Dictionary::new(1, b"not a trained dictionary".to_vec());
+        let err = compress_stream_enveloped(
+            &dictionary,
+            &b"data"[..],
+            Cursor::new(Vec::new()),
+            3,
+            &metadata(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_unknown_input_is_rejected() {
+        let err = decompress_stream_detect(&b"just text"[..], io::sink(), &Default::default())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::decompress::LimitExceeded;
+use crate::progress::Cancelled;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What went wrong while compressing or decompressing, and where.
+///
+/// Converts to and from `io::Error`, so it can pass through functions that
+/// return `io::Result` and be recovered on the other side.
+#[derive(Debug)]
+pub enum CompressionError {
+    /// An option was rejected before any data was processed.
+    InvalidOption {
+        option: &'static str,
+        message: String,
+    },
+    /// Reading the input failed. `path` is set when the input is a file.
+    Read {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// Writing the output failed. `path` is set when the output is a file.
+    Write {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// The codec itself failed, e.g. on corrupt compressed data.
+    Codec { codec: String, source: io::Error },
+    /// Decompression stopped at one of its [`crate::DecompressionLimits`].
+    LimitExceeded(LimitExceeded),
+    /// The job was cancelled through its [`crate::CancellationToken`].
+    Cancelled,
+    /// An I/O failure from a function that does not report its stage.
+    Io(io::Error),
+}
+
+impl CompressionError {
+    pub fn read(path: impl AsRef<Path>, source: io::Error) -> Self {
+        CompressionError::Read {
+            path: Some(path.as_ref().to_path_buf()),
+            source,
+        }
+    }
+
+    pub fn write(path: impl AsRef<Path>, source: io::Error) -> Self {
+        CompressionError::Write {
+            path: Some(path.as_ref().to_path_buf()),
+            source,
+        }
+    }
+
+    /// Fills in the input and output paths of read and write failures that
+    /// happened on streams opened from those files.
+    pub fn with_paths(self, input: impl AsRef<Path>, output: impl AsRef<Path>) -> Self {
+        match self {
+            CompressionError::Read { path: None, source } => Self::read(input, source),
+            CompressionError::Write { path: None, source } => Self::write(output, source),
+            other => other,
+        }
+    }
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionError::InvalidOption { option, message } => {
+                write!(f, "invalid {}: {}", option, message)
+            }
+            CompressionError::Read {
+                path: Some(path),
+                source,
+            } => write!(f, "failed to read {}: {}", path.display(), source),
+            CompressionError::Read { path: None, source } => {
+                write!(f, "failed to read input: {}", source)
+            }
+            CompressionError::Write {
+                path: Some(path),
+                source,
+            } => write!(f, "failed to write {}: {}", path.display(), source),
+            CompressionError::Write { path: None, source } => {
+                write!(f, "failed to write output: {}", source)
+            }
+            CompressionError::Codec { codec, source } => write!(f, "{} failed: {}", codec, source),
+            CompressionError::LimitExceeded(exceeded) => exceeded.fmt(f),
+            CompressionError::Cancelled => Cancelled.fmt(f),
+            CompressionError::Io(source) => source.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for CompressionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompressionError::Read { source, .. }
+            | CompressionError::Write { source, .. }
+            | CompressionError::Codec { source, .. }
+            | CompressionError::Io(source) => Some(source),
+            CompressionError::LimitExceeded(exceeded) => Some(exceeded),
+            CompressionError::InvalidOption { .. } | CompressionError::Cancelled => None,
+        }
+    }
+}
+
+impl From<CompressionError> for io::Error {
+    fn from(error: CompressionError) -> Self {
+        match error {
+            // Wrapped the way the rest of the crate wraps them, so checks
+            // like `is_cancelled` keep working
+            CompressionError::LimitExceeded(exceeded) => {
+                io::Error::new(io::ErrorKind::InvalidData, exceeded)
+            }
+            CompressionError::Cancelled => io::Error::other(Cancelled),
+            CompressionError::Io(source) => source,
+            CompressionError::InvalidOption { .. } => {
+                io::Error::new(io::ErrorKind::InvalidInput, error)
+            }
+            CompressionError::Read { ref source, .. }
+            | CompressionError::Write { ref source, .. }
+            | CompressionError::Codec { ref source, .. } => io::Error::new(source.kind(), error),
+        }
+    }
+}
+
+impl From<io::Error> for CompressionError {
+    /// Recovers errors that were converted to `io::Error` on the way, and
+    /// wraps anything else as [`CompressionError::Io`].
+    fn from(error: io::Error) -> Self {
+        if error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<CompressionError>())
+        {
+            return *error
+                .into_inner()
+                .and_then(|inner| inner.downcast().ok())
+                .expect("checked above");
+        }
+        if let Some(exceeded) = error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<LimitExceeded>())
+        {
+            return CompressionError::LimitExceeded(exceeded.clone());
+        }
+        if crate::progress::is_cancelled(&error) {
+            return CompressionError::Cancelled;
+        }
+        CompressionError::Io(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trips_through_io_error() {
+        let error = CompressionError::read("input.txt", io::ErrorKind::NotFound.into());
+        let io_error = io::Error::from(error);
+        assert_eq!(io_error.kind(), io::ErrorKind::NotFound);
+        assert!(matches!(
+            CompressionError::from(io_error),
+            CompressionError::Read { path: Some(path), .. } if path == Path::new("input.txt")
+        ));
+
+        let cancelled = io::Error::from(CompressionError::Cancelled);
+        assert!(crate::progress::is_cancelled(&cancelled));
+        assert!(matches!(
+            CompressionError::from(cancelled),
+            CompressionError::Cancelled
+        ));
+
+        let exceeded = io::Error::new(
+            io::ErrorKind::InvalidData,
+            LimitExceeded::OutputSize { limit: 10 },
+        );
+        assert!(matches!(
+            CompressionError::from(exceeded),
+            CompressionError::LimitExceeded(LimitExceeded::OutputSize { limit: 10 })
+        ));
+    }
+}
@@ -0,0 +1,659 @@
+use crate::codec::{Codec, Encoder};
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{
+    self, Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError, TrySendError,
+};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long an external command may run unless the codec says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Placeholder replaced by the compression level in compress arguments.
+pub const LEVEL_PLACEHOLDER: &str = "{level}";
+
+/// Size of the chunks passed to and from the external program.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks queued to and from the program. When they are full the program
+/// blocks on its pipes, so a fast program cannot run ahead of the caller.
+const QUEUED_CHUNKS: usize = 4;
+
+/// How long to wait for output while the program's input is full.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// At most this much of the program's stderr ends up in error messages.
+const STDERR_LIMIT: u64 = 4 * 1024;
+
+/// A program and its arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalCommand {
+    program: PathBuf,
+    args: Vec<String>,
+}
+
+impl ExternalCommand {
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Adds an argument. In compress commands, [`LEVEL_PLACEHOLDER`] in an
+    /// argument is replaced by the compression level.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    fn spawn(&self, level: Option<u32>) -> io::Result<Child> {
+        let args = self.args.iter().map(|arg| match level {
+            Some(level) => OsString::from(arg.replace(LEVEL_PLACEHOLDER, &level.to_string())),
+            None => OsString::from(arg),
+        });
+
+        Command::new(&self.program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("failed to start {}: {}", self.program.display(), e),
+                )
+            })
+    }
+}
+
+/// A codec that streams data through external programs over stdin and
+/// stdout, for formats with no Rust implementation.
+///
+/// A run fails if the program exits with a non-zero status or takes longer
+/// than the timeout, in which case it is killed. The name and extension are
+/// `&'static str` like every other codec, so codecs configured at runtime
+/// have to leak them.
+#[derive(Debug, Clone)]
+pub struct ExternalCodec {
+    name: &'static str,
+    extension: &'static str,
+    compress: ExternalCommand,
+    decompress: ExternalCommand,
+    levels: RangeInclusive<u32>,
+    default_level: u32,
+    timeout: Duration,
+}
+
+impl ExternalCodec {
+    /// A codec with a single level, 0, and [`DEFAULT_TIMEOUT`].
+    pub fn new(
+        name: &'static str,
+        extension: &'static str,
+        compress: ExternalCommand,
+        decompress: ExternalCommand,
+    ) -> Self {
+        Self {
+            name,
+            extension,
+            compress,
+            decompress,
+            levels: 0..=0,
+            default_level: 0,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets the accepted levels, which the compress command receives through
+    /// [`LEVEL_PLACEHOLDER`]. `default_level` must lie within `levels`.
+    pub fn with_levels(mut self, levels: RangeInclusive<u32>, default_level: u32) -> Self {
+        assert!(
+            levels.contains(&default_level),
+            "default level {} is outside {:?}",
+            default_level,
+            levels
+        );
+        self.levels = levels;
+        self.default_level = default_level;
+        self
+    }
+
+    /// Sets how long a single compress or decompress run may take.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+
+impl Codec for ExternalCodec {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn extension(&self) -> &'static str {
+        self.extension
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        self.levels.clone()
+    }
+
+    fn default_level(&self) -> u32 {
+        self.default_level
+    }
+
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>> {
+        self.validate_level(level)?;
+        Ok(Box::new(ExternalEncoder {
+            process: Process::spawn(&self.compress, Some(level), self.timeout)?,
+            writer,
+        }))
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(Box::new(ExternalDecoder {
+            process: Process::spawn(&self.decompress, None, self.timeout)?,
+            reader,
+            input: vec![0; CHUNK_SIZE],
+            unsent: None,
+            reader_done: false,
+            pending: Vec::new(),
+            position: 0,
+            finished: false,
+        }))
+    }
+}
+
+/// A running external program. Its stdin and stdout are passed through
+/// threads and bounded queues, so neither side blocks the other for good
+/// and memory use stays bounded, its stderr is drained by another thread,
+/// and a watchdog kills it when the timeout runs out.
+struct Process {
+    program: String,
+    child: Arc<Mutex<Child>>,
+    stdin: Option<SyncSender<Vec<u8>>>,
+    stdout: Receiver<io::Result<Vec<u8>>>,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+    deadline: Instant,
+    timed_out: Arc<AtomicBool>,
+    // Dropping this stops the watchdog
+    _watchdog: Sender<()>,
+    exited: bool,
+}
+
+impl Process {
+    fn spawn(command: &ExternalCommand, level: Option<u32>, timeout: Duration) -> io::Result<Self> {
+        let mut child = command.spawn(level)?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let child = Arc::new(Mutex::new(child));
+
+        // The thread stops on a write error, which the next send sees as
+        // the queue being disconnected
+        let (input, to_write) = mpsc::sync_channel::<Vec<u8>>(QUEUED_CHUNKS);
+        thread::spawn(move || {
+            for chunk in to_write {
+                if stdin.write_all(&chunk).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (output, received) = mpsc::sync_channel(QUEUED_CHUNKS);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            match stdout.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    if output.send(Ok(chunk)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let _ = output.send(Err(e));
+                    break;
+                }
+            }
+        });
+
+        let stderr = thread::spawn(move || {
+            let mut stderr = stderr;
+            let mut message = Vec::new();
+            let _ = (&mut stderr).take(STDERR_LIMIT).read_to_end(&mut message);
+            // Keep draining so the program never blocks on a full pipe
+            let _ = io::copy(&mut stderr, &mut io::sink());
+            message
+        });
+
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let (watchdog, stop) = mpsc::channel::<()>();
+        {
+            let child = Arc::clone(&child);
+            let timed_out = Arc::clone(&timed_out);
+            thread::spawn(move || {
+                if let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(timeout) {
+                    timed_out.store(true, Ordering::Relaxed);
+                    let _ = child.lock().unwrap().kill();
+                }
+            });
+        }
+
+        Ok(Self {
+            program: command.program.display().to_string(),
+            child,
+            stdin: Some(input),
+            stdout: received,
+            stderr: Some(stderr),
+            deadline: Instant::now() + timeout,
+            timed_out,
+            _watchdog: watchdog,
+            exited: false,
+        })
+    }
+
+    fn input_open(&self) -> bool {
+        self.stdin.is_some()
+    }
+
+    /// Queues `chunk` for the program's stdin, handing it back if the
+    /// queue is full.
+    fn try_input(&mut self, chunk: Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        let stdin = self.stdin.as_ref().expect("input is open");
+        match stdin.try_send(chunk) {
+            Ok(()) => Ok(None),
+            Err(TrySendError::Full(chunk)) => Ok(Some(chunk)),
+            Err(TrySendError::Disconnected(_)) => {
+                // A program that exits early closes its stdin, and its exit
+                // status says more than the broken pipe
+                self.stdin = None;
+                self.wait()?;
+                Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    format!("{} stopped reading its input", self.program),
+                ))
+            }
+        }
+    }
+
+    fn close_input(&mut self) {
+        self.stdin = None;
+    }
+
+    /// Returns output the program has already produced, without waiting.
+    fn try_output(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match self.stdout.try_recv() {
+            Ok(chunk) => chunk.map(Some),
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => Ok(None),
+        }
+    }
+
+    /// Waits briefly for output, for while the program's input is full.
+    fn poll_output(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        match self.stdout.recv_timeout(remaining.min(POLL_INTERVAL)) {
+            Ok(chunk) => chunk.map(Some),
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(remaining.min(POLL_INTERVAL));
+                Ok(None)
+            }
+            Err(RecvTimeoutError::Timeout) if remaining.is_zero() => {
+                self.kill();
+                Err(self.timeout_error())
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+        }
+    }
+
+    /// Waits for the next output, returning `None` once stdout is closed.
+    fn next_output(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        match self.stdout.recv_timeout(remaining) {
+            Ok(chunk) => chunk.map(Some),
+            Err(RecvTimeoutError::Disconnected) => Ok(None),
+            Err(RecvTimeoutError::Timeout) => {
+                self.kill();
+                Err(self.timeout_error())
+            }
+        }
+    }
+
+    /// Waits for the program to exit and checks its status.
+    fn wait(&mut self) -> io::Result<()> {
+        let status = loop {
+            if let Some(status) = self.child.lock().unwrap().try_wait()? {
+                break status;
+            }
+            if Instant::now() >= self.deadline {
+                self.kill();
+                return Err(self.timeout_error());
+            }
+            thread::sleep(Duration::from_millis(5));
+        };
+        self.exited = true;
+
+        if self.timed_out.load(Ordering::Relaxed) {
+            return Err(self.timeout_error());
+        }
+        if status.success() {
+            Ok(())
+        } else {
+            Err(self.status_error(status))
+        }
+    }
+
+    fn kill(&mut self) {
+        self.timed_out.store(true, Ordering::Relaxed);
+        let mut child = self.child.lock().unwrap();
+        let _ = child.kill();
+        let _ = child.wait();
+        self.exited = true;
+    }
+
+    fn timeout_error(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{} did not finish in time and was killed", self.program),
+        )
+    }
+
+    fn status_error(&mut self, status: ExitStatus) -> io::Error {
+        let stderr = self
+            .stderr
+            .take()
+            .and_then(|stderr| stderr.join().ok())
+            .unwrap_or_default();
+        let stderr = String::from_utf8_lossy(&stderr);
+        let stderr = stderr.trim();
+
+        if stderr.is_empty() {
+            io::Error::other(format!("{} exited with {}", self.program, status))
+        } else {
+            io::Error::other(format!(
+                "{} exited with {}: {}",
+                self.program, status, stderr
+            ))
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // Abandoned runs must not leave the program behind
+        if !self.exited {
+            self.stdin = None;
+            let mut child = self.child.lock().unwrap();
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+struct ExternalEncoder<'a> {
+    process: Process,
+    writer: Box<dyn Write + 'a>,
+}
+
+impl ExternalEncoder<'_> {
+    /// Passes on whatever output the program has produced so far.
+    fn drain(&mut self) -> io::Result<()> {
+        while let Some(chunk) = self.process.try_output()? {
+            self.writer.write_all(&chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for ExternalEncoder<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(CHUNK_SIZE);
+        let mut chunk = buf[..n].to_vec();
+        // Output is passed on while waiting, or the program could block
+        // on its stdout and never make room
+        while let Some(unsent) = self.process.try_input(chunk)? {
+            chunk = unsent;
+            if let Some(output) = self.process.poll_output()? {
+                self.writer.write_all(&output)?;
+            }
+        }
+        self.drain()?;
+        Ok(n)
+    }
+
+    /// Flushes what the program has output so far. The program itself
+    /// cannot be asked to flush.
+    fn flush(&mut self) -> io::Result<()> {
+        self.drain()?;
+        self.writer.flush()
+    }
+}
+
+impl Encoder for ExternalEncoder<'_> {
+    fn finish(mut self: Box<Self>) -> io::Result<()> {
+        self.process.close_input();
+        while let Some(chunk) = self.process.next_output()? {
+            self.writer.write_all(&chunk)?;
+        }
+        self.process.wait()?;
+        self.writer.flush()
+    }
+}
+
+struct ExternalDecoder<'a> {
+    process: Process,
+    reader: Box<dyn Read + 'a>,
+    input: Vec<u8>,
+    /// Input read but not yet queued for the program.
+    unsent: Option<Vec<u8>>,
+    reader_done: bool,
+    pending: Vec<u8>,
+    position: usize,
+    finished: bool,
+}
+
+impl Read for ExternalDecoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position < self.pending.len() {
+                let n = buf.len().min(self.pending.len() - self.position);
+                buf[..n].copy_from_slice(&self.pending[self.position..self.position + n]);
+                self.position += n;
+                return Ok(n);
+            }
+            if self.finished {
+                return Ok(0);
+            }
+
+            // Feed the program until it has output to hand back, then wait
+            // for the rest once the input is exhausted. Output is only
+            // taken as fast as the caller reads it.
+            let chunk = if self.process.input_open() {
+                match self.process.try_output()? {
+                    Some(chunk) => Some(chunk),
+                    None => {
+                        if self.unsent.is_none() && !self.reader_done {
+                            let n = self.reader.read(&mut self.input)?;
+                            if n == 0 {
+                                self.reader_done = true;
+                            } else {
+                                self.unsent = Some(self.input[..n].to_vec());
+                            }
+                        }
+                        match self.unsent.take() {
+                            Some(input) => match self.process.try_input(input)? {
+                                Some(input) => {
+                                    self.unsent = Some(input);
+                                    self.process.poll_output()?
+                                }
+                                None => None,
+                            },
+                            None => {
+                                self.process.close_input();
+                                None
+                            }
+                        }
+                    }
+                }
+            } else {
+                match self.process.next_output()? {
+                    Some(chunk) => Some(chunk),
+                    None => {
+                        self.process.wait()?;
+                        self.finished = true;
+                        None
+                    }
+                }
+            };
+            if let Some(chunk) = chunk {
+                self.pending = chunk;
+                self.position = 0;
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::codec::Gzip;
+    use crate::{
+        compress_stream_with, compress_stream_with_options, decompress_stream_with,
+        CompressionError, CompressionOptions, DecompressionLimits,
+    };
+
+    fn external_gzip() -> ExternalCodec {
+        ExternalCodec::new(
+            "external-gzip",
+            "gz",
+            ExternalCommand::new("gzip").args(["-c", "-{level}"]),
+            ExternalCommand::new("gzip").args(["-d", "-c"]),
+        )
+        .with_levels(1..=9, 6)
+    }
+
+    #[test]
+    fn test_round_trip_through_external_program() -> io::Result<()> {
+        // Larger than a pipe buffer in both directions
+        let data: Vec<u8> = (0..2_000_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let codec = external_gzip();
+
+        let mut compressed = Vec::new();
+        let report = compress_stream_with(&codec, &data[..], &mut compressed, 9)?;
+        assert_eq!(report.bytes_read, data.len() as u64);
+
+        // The output is real gzip, and the external decoder reads it back
+        for decoder in [&codec as &dyn Codec, &Gzip] {
+            let mut decoded = Vec::new();
+            decompress_stream_with(
+                decoder,
+                &compressed[..],
+                &mut decoded,
+                &DecompressionLimits::default(),
+            )?;
+            assert!(
+                decoded == data,
+                "{} decoded different bytes",
+                decoder.name()
+            );
+        }
+
+        assert!(compress_stream_with(&codec, &data[..], Vec::new(), 10).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_output_is_read_as_fast_as_the_caller_reads() -> io::Result<()> {
+        // Far more output than input, read in small pieces, so the program
+        // keeps blocking on its stdout
+        let data = vec![0u8; 20_000_000];
+        let mut compressed = Vec::new();
+        compress_stream_with(&Gzip, &data[..], &mut compressed, 9)?;
+
+        let mut decoder = external_gzip().decoder(Box::new(&compressed[..]))?;
+        let mut buf = [0u8; 1000];
+        let mut total = 0;
+        loop {
+            let n = decoder.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            assert!(buf[..n].iter().all(|&b| b == 0));
+            total += n;
+        }
+        assert_eq!(total, data.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_exit_status_is_reported() {
+        let failing = ExternalCodec::new(
+            "failing",
+            "bin",
+            ExternalCommand::new("sh").args(["-c", "cat >/dev/null; echo broken >&2; exit 3"]),
+            ExternalCommand::new("sh").args(["-c", "exit 4"]),
+        );
+
+        let options = CompressionOptions::builder()
+            .codec(&failing)
+            .build()
+            .unwrap();
+        let error = compress_stream_with_options(&b"data"[..], Vec::new(), &options).unwrap_err();
+        assert!(matches!(error, CompressionError::Codec { .. }));
+        let message = error.to_string();
+        assert!(message.contains("exit status: 3"), "{}", message);
+        assert!(message.contains("broken"), "{}", message);
+
+        let error = decompress_stream_with(
+            &failing,
+            &b"data"[..],
+            Vec::new(),
+            &DecompressionLimits::default(),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("exit status: 4"), "{}", error);
+    }
+
+    #[test]
+    fn test_slow_program_is_killed() {
+        let slow = ExternalCodec::new(
+            "slow",
+            "bin",
+            ExternalCommand::new("sleep").arg("10"),
+            ExternalCommand::new("sleep").arg("10"),
+        )
+        .with_timeout(Duration::from_millis(200));
+
+        let started = Instant::now();
+        let error = compress_stream_with(&slow, &b"data"[..], Vec::new(), 0).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::codec::{Codec, Encoder, Gzip};
+use crate::decompress::{decompress_file_with, DecompressionLimits};
+use filetime::FileTime;
+use flate2::bufread::GzDecoder;
+use flate2::{Compression, GzBuilder};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Optional fields of a gzip header.
+///
+/// Also a [`Codec`] that writes gzip with these fields set, so it works
+/// with every `*_with` function. Names and comments are written as UTF-8,
+/// as GNU gzip does, and read back as Latin-1 when they are not valid
+/// UTF-8.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GzipHeader {
+    /// Original file name (FNAME).
+    pub file_name: Option<String>,
+    /// Modification time of the original file in Unix seconds (MTIME).
+    /// gzip stores `0` for "not recorded", which reads back as `None`.
+    pub mtime: Option<u32>,
+    /// Free-form comment (FCOMMENT).
+    pub comment: Option<String>,
+}
+
+impl GzipHeader {
+    /// Header carrying the name and modification time of `path`.
+    pub fn for_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mtime = fs::metadata(path)?
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .and_then(|since_epoch| u32::try_from(since_epoch.as_secs()).ok())
+            .filter(|&secs| secs != 0);
+
+        Ok(Self {
+            file_name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned()),
+            mtime,
+            comment: None,
+        })
+    }
+
+    fn builder(&self) -> io::Result<GzBuilder> {
+        let mut builder = GzBuilder::new().mtime(self.mtime.unwrap_or(0));
+        if let Some(file_name) = &self.file_name {
+            builder = builder.filename(zero_terminated_field("file name", file_name)?);
+        }
+        if let Some(comment) = &self.comment {
+            builder = builder.comment(zero_terminated_field("comment", comment)?);
+        }
+        Ok(builder)
+    }
+}
+
+/// Checks that `value` can be stored as a zero-terminated header field.
+fn zero_terminated_field<'a>(field: &str, value: &'a str) -> io::Result<&'a str> {
+    if value.contains('\0') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("gzip {} must not contain NUL bytes", field),
+        ));
+    }
+    Ok(value)
+}
+
+/// Decodes a header field, falling back to Latin-1 for non-UTF-8 bytes.
+fn decode_field(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&byte| byte as char).collect(),
+    }
+}
+
+impl Codec for GzipHeader {
+    fn name(&self) -> &'static str {
+        Gzip.name()
+    }
+
+    fn extension(&self) -> &'static str {
+        Gzip.extension()
+    }
+
+    fn levels(&self) -> RangeInclusive<u32> {
+        Gzip.levels()
+    }
+
+    fn default_level(&self) -> u32 {
+        Gzip.default_level()
+    }
+
+    fn encoder<'a>(
+        &self,
+        writer: Box<dyn Write + 'a>,
+        level: u32,
+    ) -> io::Result<Box<dyn Encoder + 'a>> {
+        self.validate_level(level)?;
+        Ok(Box::new(
+            self.builder()?.write(writer, Compression::new(level)),
+        ))
+    }
+
+    fn decoder<'a>(&self, reader: Box<dyn Read + 'a>) -> io::Result<Box<dyn Read + 'a>> {
+        Gzip.decoder(reader)
+    }
+}
+
+/// Reads the header of the first member of a gzip file.
+pub fn read_gzip_header(gzip_file: &str) -> io::Result<GzipHeader> {
+    let decoder = GzDecoder::new(BufReader::new(File::open(gzip_file)?));
+    let header = decoder.header().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} does not start with a valid gzip header", gzip_file),
+        )
+    })?;
+
+    Ok(GzipHeader {
+        file_name: header.filename().map(decode_field),
+        mtime: Some(header.mtime()).filter(|&mtime| mtime != 0),
+        comment: header.comment().map(decode_field),
+    })
+}
+
+/// Decompresses a gzip file into `output_dir`, like `gunzip -N`: the output
+/// takes the name and modification time stored in the header.
+///
+/// Only the last component of the stored name is used, so a crafted header
+/// cannot write outside `output_dir`. Without a usable name the input name
+/// minus its `.gz` extension is used. Returns the path written.
+pub fn decompress_gzip_restoring(
+    gzip_file: &str,
+    output_dir: &Path,
+    limits: &DecompressionLimits,
+) -> io::Result<PathBuf> {
+    let header = read_gzip_header(gzip_file)?;
+    let stored_name = header
+        .file_name
+        .as_deref()
+        .and_then(|name| Path::new(name).file_name());
+    let fallback = Path::new(gzip_file);
+    let fallback = match fallback.extension() {
+        Some(extension) if extension.eq_ignore_ascii_case("gz") => fallback.file_stem(),
+        _ => fallback.file_name(),
+    };
+    let file_name = stored_name.or(fallback).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("cannot derive an output name for {}", gzip_file),
+        )
+    })?;
+
+    let output_path = output_dir.join(file_name);
+    let output_file = output_path.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not valid UTF-8", output_path.display()),
+        )
+    })?;
+    decompress_file_with(&Gzip, gzip_file, output_file, limits)?;
+
+    if let Some(mtime) = header.mtime {
+        filetime::set_file_mtime(&output_path, FileTime::from_unix_time(mtime as i64, 0))?;
+    }
+    Ok(output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress_file_with;
+
+    #[test]
+    fn test_header_fields_round_trip() -> io::Result<()> {
+        let input_path = "test_gzip_header_input.txt";
+        let output_path = "test_gzip_header_output.gz";
+        fs::write(input_path, b"header test")?;
+
+        let header = GzipHeader {
+            file_name: Some("report.txt".to_string()),
+            mtime: Some(1_700_000_000),
+            comment: Some("task 42".to_string()),
+        };
+        compress_file_with(&header, input_path, output_path, 6)?;
+        assert_eq!(read_gzip_header(output_path)?, header);
+
+        // The default header leaves every field unset
+        compress_file_with(&Gzip, input_path, output_path, 6)?;
+        assert_eq!(read_gzip_header(output_path)?, GzipHeader::default());
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+        fs::remove_file(output_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompress_restores_name_and_mtime() -> io::Result<()> {
+        let input_path = "test_gzip_restore_input.txt";
+        let output_path = "test_gzip_restore_output.gz";
+        let out_dir = Path::new("test_gzip_restore_dir");
+        fs::write(input_path, b"restore me")?;
+        fs::create_dir_all(out_dir)?;
+
+        // A path in the stored name must not escape the output directory
+        let header = GzipHeader {
+            file_name: Some("../original.txt".to_string()),
+            mtime: Some(1_600_000_000),
+            comment: None,
+        };
+        compress_file_with(&header, input_path, output_path, 6)?;
+
+        let restored =
+            decompress_gzip_restoring(output_path, out_dir, &DecompressionLimits::default())?;
+        assert_eq!(restored, out_dir.join("original.txt"));
+        assert_eq!(fs::read(&restored)?, b"restore me");
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(&restored)?);
+        assert_eq!(mtime.unix_seconds(), 1_600_000_000);
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+        fs::remove_file(output_path)?;
+        fs::remove_dir_all(out_dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_nul_in_fields() {
+        let header = GzipHeader {
+            file_name: Some("bad\0name".to_string()),
+            ..GzipHeader::default()
+        };
+        let err = crate::compress_stream_with(&header, &b"data"[..], Vec::new(), 6).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,34 +1,189 @@
-use flate2::write::GzEncoder;
-use flate2::Compression;
+pub mod archive;
+pub mod auto;
+pub mod checksum;
+pub mod codec;
+pub mod decompress;
+#[cfg(feature = "zstd")]
+pub mod dictionary;
+pub mod envelope;
+pub mod error;
+pub mod external;
+pub mod gzip_header;
+pub mod options;
+pub mod parallel;
+pub mod progress;
+pub mod recovery;
+pub mod seekable;
+pub mod transcode;
+pub mod volumes;
+
+use codec::Codec;
 use std::fs::File;
-use std::io::{self, Read, Write};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+pub use archive::{create_archive, extract_archive, list_archive, ArchiveEntry, ArchiveFormat};
+pub use auto::{
+    choose_codec, compress_file_auto, compress_file_auto_with_progress, AutoOptions, AutoOutcome,
+    Decision, SkipReason,
+};
+pub use checksum::{
+    compress_file_checked, compress_stream_checked, sha256_file, verify_compressed_file,
+    ChecksumReport,
+};
+pub use codec::{codec_by_extension, codec_by_name, codecs};
+pub use decompress::{
+    decompress_file, decompress_file_with, decompress_stream, decompress_stream_with,
+    DecompressionLimits, LimitExceeded, LimitedDecoder,
+};
+#[cfg(feature = "zstd")]
+pub use dictionary::{Dictionary, DictionaryStore};
+pub use envelope::{
+    compress_file_enveloped, compress_stream_enveloped, decompress_file_detect,
+    decompress_stream_detect, read_envelope_header, Detected, EnvelopeHeader,
+};
+pub use error::CompressionError;
+pub use external::{ExternalCodec, ExternalCommand};
+pub use gzip_header::{decompress_gzip_restoring, read_gzip_header, GzipHeader};
+pub use options::{
+    compress_file_with_options, compress_stream_with_options, CompressionOptions,
+    CompressionOptionsBuilder,
+};
+pub use parallel::{compress_file_parallel, compress_stream_parallel, ParallelOptions};
+#[cfg(feature = "async")]
+pub use progress::compress_file_async;
+pub use progress::{compress_file_with_progress, CancellationToken, Progress, ProgressReader};
+pub use recovery::{
+    recovery_path, repair_file, write_recovery_record, RecoveryOptions, RecoveryReport,
+    RepairReport,
+};
+pub use seekable::{
+    compress_file_seekable, compress_stream_seekable, read_file_range, SeekIndex, SeekableReader,
+};
+pub use transcode::{transcode_file, transcode_stream, TranscodeReport};
+pub use volumes::{
+    compress_file_to_volumes, decompress_volumes, decompress_volumes_stream, manifest_path, Volume,
+    VolumeManifest, VolumeReader, VolumeWriter,
+};
+
+/// Size of the buffer used to copy data through an encoder.
+pub(crate) const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Summary of a finished compression run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionReport {
+    /// Number of uncompressed bytes read from the input.
+    pub bytes_read: u64,
+    /// Number of compressed bytes written to the output.
+    pub bytes_written: u64,
+}
+
+/// Writer wrapper that counts the bytes passed through it.
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    pub(crate) count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Copies `reader` into `writer` in fixed-size chunks, returning the number
+/// of bytes copied.
+pub(crate) fn copy_stream<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<u64> {
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut copied = 0u64;
+
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(copied),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..n])?;
+        copied += n as u64;
+    }
+}
+
+/// Compresses everything from `reader` into `writer` using gzip.
+///
+/// Data is copied through the encoder in fixed-size chunks, so memory use
+/// does not depend on the input size. The gzip trailer is always written and
+/// the writer flushed before returning. Levels outside 0 to 9 are rejected
+/// with [`CompressionError::InvalidOption`].
+pub fn compress_stream<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    compression_level: u32,
+) -> Result<CompressionReport, CompressionError> {
+    let options = CompressionOptions::builder()
+        .level(compression_level)
+        .build()?;
+    compress_stream_with_options(reader, writer, &options)
+}
+
+/// Like [`compress_stream`], using `codec`. Fails with `InvalidInput` if
+/// `compression_level` is outside the codec's level range.
+pub fn compress_stream_with<R: Read, W: Write>(
+    codec: &dyn Codec,
+    mut reader: R,
+    writer: W,
+    compression_level: u32,
+) -> io::Result<CompressionReport> {
+    let mut output = CountingWriter::new(writer);
+    let mut encoder = codec.encoder(Box::new(&mut output), compression_level)?;
+    let bytes_read = copy_stream(&mut reader, &mut encoder)?;
+    encoder.finish()?;
 
-/// Compresses a file using gzip and saves it in the `compressed` directory
-/// Allows specifying the compression level.
+    Ok(CompressionReport {
+        bytes_read,
+        bytes_written: output.count,
+    })
+}
+
+/// Compresses a file using gzip at the given level. Levels outside 0 to 9
+/// are rejected with [`CompressionError::InvalidOption`].
 pub fn compress_file(
     input_file: &str,
     output_file: &str,
     compression_level: u32,
-) -> io::Result<()> {
-    let mut input = File::open(input_file)?;
-    let mut data = Vec::new();
-    input.read_to_end(&mut data)?;
-
-    let output = File::create(output_file)?;
-    let compression = match compression_level {
-        0 => Compression::none(),
-        1..=9 => Compression::new(compression_level),
-        _ => Compression::default(),
-    };
-    let mut encoder = GzEncoder::new(output, compression);
-    encoder.write_all(&data)?;
-
-    Ok(())
+) -> Result<CompressionReport, CompressionError> {
+    let options = CompressionOptions::builder()
+        .level(compression_level)
+        .build()?;
+    compress_file_with_options(input_file, output_file, &options)
+}
+
+/// Like [`compress_file`], using `codec`.
+pub fn compress_file_with(
+    codec: &dyn Codec,
+    input_file: &str,
+    output_file: &str,
+    compression_level: u32,
+) -> io::Result<CompressionReport> {
+    let input = BufReader::new(File::open(input_file)?);
+    let output = BufWriter::new(File::create(output_file)?);
+    compress_stream_with(codec, input, output, compression_level)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use flate2::read::GzDecoder;
     use std::fs;
     use std::io::{self, Write};
 
@@ -100,4 +255,60 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_compress_file_rejects_out_of_range_level() {
+        let input_path = "test_input_bad_level.txt";
+        let output_path = "test_output_bad_level.gz";
+
+        // The level is checked before either file is touched
+        let result = compress_file(input_path, output_path, 10);
+        assert!(matches!(
+            result,
+            Err(CompressionError::InvalidOption {
+                option: "level",
+                ..
+            })
+        ));
+        assert!(!std::path::Path::new(output_path).exists());
+    }
+
+    #[test]
+    fn test_compress_stream_reports_sizes_and_round_trips() -> io::Result<()> {
+        // Larger than one copy buffer so the loop runs several times
+        let data: Vec<u8> = (0..BUFFER_SIZE * 3 + 17).map(|i| (i % 251) as u8).collect();
+        let mut compressed = Vec::new();
+
+        let report = compress_stream(&data[..], &mut compressed, 6)?;
+        assert_eq!(report.bytes_read, data.len() as u64);
+        assert_eq!(report.bytes_written, compressed.len() as u64);
+
+        // A finished stream decodes back to the original bytes
+        let mut decoded = Vec::new();
+        GzDecoder::new(&compressed[..]).read_to_end(&mut decoded)?;
+        assert_eq!(decoded, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compress_stream_surfaces_write_errors() {
+        struct FailingWriter;
+
+        impl Write for FailingWriter {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk full"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let result = compress_stream(&b"some data"[..], FailingWriter, 6);
+        assert!(
+            result.is_err(),
+            "Trailer write errors must not be swallowed"
+        );
+    }
 }
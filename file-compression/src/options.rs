@@ -0,0 +1,273 @@
+use crate::codec::{codec_by_name, Codec, Gzip};
+use crate::error::CompressionError;
+use crate::{compress_stream_with, CompressionReport};
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+/// A codec and a level that has been checked against it. Built with
+/// [`CompressionOptions::builder`].
+#[derive(Clone, Copy)]
+pub struct CompressionOptions<'a> {
+    codec: &'a dyn Codec,
+    level: u32,
+}
+
+impl<'a> CompressionOptions<'a> {
+    pub fn builder() -> CompressionOptionsBuilder<'a> {
+        CompressionOptionsBuilder::default()
+    }
+
+    pub fn codec(&self) -> &'a dyn Codec {
+        self.codec
+    }
+
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+}
+
+impl std::fmt::Debug for CompressionOptions<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompressionOptions")
+            .field("codec", &self.codec.name())
+            .field("level", &self.level)
+            .finish()
+    }
+}
+
+#[derive(Default)]
+enum CodecChoice<'a> {
+    #[default]
+    Default,
+    Codec(&'a dyn Codec),
+    Name(String),
+}
+
+/// Builder for [`CompressionOptions`]. The codec defaults to gzip and the
+/// level to the codec's default level.
+#[derive(Default)]
+pub struct CompressionOptionsBuilder<'a> {
+    codec: CodecChoice<'a>,
+    level: Option<u32>,
+}
+
+impl<'a> CompressionOptionsBuilder<'a> {
+    pub fn codec(mut self, codec: &'a dyn Codec) -> Self {
+        self.codec = CodecChoice::Codec(codec);
+        self
+    }
+
+    /// Looks the codec up with [`codec_by_name`] when building.
+    pub fn codec_name(mut self, name: &str) -> Self {
+        self.codec = CodecChoice::Name(name.to_string());
+        self
+    }
+
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Fails with [`CompressionError::InvalidOption`] if the codec is not
+    /// available or the level is outside its range.
+    pub fn build(self) -> Result<CompressionOptions<'a>, CompressionError> {
+        let codec: &'a dyn Codec = match self.codec {
+            CodecChoice::Default => &Gzip,
+            CodecChoice::Codec(codec) => codec,
+            CodecChoice::Name(name) => {
+                codec_by_name(&name).ok_or_else(|| CompressionError::InvalidOption {
+                    option: "codec",
+                    message: format!("{} is not available in this build", name),
+                })?
+            }
+        };
+        let level = self.level.unwrap_or_else(|| codec.default_level());
+        codec
+            .validate_level(level)
+            .map_err(|e| CompressionError::InvalidOption {
+                option: "level",
+                message: e.to_string(),
+            })?;
+
+        Ok(CompressionOptions { codec, level })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    Read,
+    Write,
+}
+
+/// Records a failure of the wrapped reader or writer, so an error coming
+/// out of the codec can be traced back to where it started.
+struct Staged<'s, T> {
+    inner: T,
+    stage: Stage,
+    failed: &'s Cell<Option<Stage>>,
+}
+
+impl<T> Staged<'_, T> {
+    fn track<V>(&self, result: io::Result<V>) -> io::Result<V> {
+        if result.is_err() {
+            self.failed.set(Some(self.stage));
+        }
+        result
+    }
+}
+
+impl<T: Read> Read for Staged<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.read(buf);
+        self.track(result)
+    }
+}
+
+impl<T: Write> Write for Staged<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.track(result)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.inner.flush();
+        self.track(result)
+    }
+}
+
+/// Like [`compress_stream_with`], reporting which stage failed.
+pub fn compress_stream_with_options<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    options: &CompressionOptions,
+) -> Result<CompressionReport, CompressionError> {
+    let failed = Cell::new(None);
+    let reader = Staged {
+        inner: reader,
+        stage: Stage::Read,
+        failed: &failed,
+    };
+    let writer = Staged {
+        inner: writer,
+        stage: Stage::Write,
+        failed: &failed,
+    };
+
+    compress_stream_with(options.codec, reader, writer, options.level).map_err(|source| {
+        // Cancellation surfaces as a read failure, but is not one
+        match (CompressionError::from(source), failed.get()) {
+            (CompressionError::Io(source), Some(Stage::Read)) => {
+                CompressionError::Read { path: None, source }
+            }
+            (CompressionError::Io(source), Some(Stage::Write)) => {
+                CompressionError::Write { path: None, source }
+            }
+            (CompressionError::Io(source), None) => CompressionError::Codec {
+                codec: options.codec.name().to_string(),
+                source,
+            },
+            (error, _) => error,
+        }
+    })
+}
+
+/// Like [`compress_stream_with_options`], for files. Read and write
+/// failures carry the path of the file involved.
+pub fn compress_file_with_options(
+    input_file: &str,
+    output_file: &str,
+    options: &CompressionOptions,
+) -> Result<CompressionReport, CompressionError> {
+    let input = File::open(input_file).map_err(|e| CompressionError::read(input_file, e))?;
+    let output = File::create(output_file).map_err(|e| CompressionError::write(output_file, e))?;
+
+    compress_stream_with_options(BufReader::new(input), BufWriter::new(output), options)
+        .map_err(|e| e.with_paths(input_file, output_file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progress::{CancellationToken, ProgressReader};
+    use std::path::Path;
+
+    #[test]
+    fn test_builder_validates_options() {
+        let options = CompressionOptions::builder().build().unwrap();
+        assert_eq!(options.codec().name(), "gzip");
+        assert_eq!(options.level(), Gzip.default_level());
+
+        assert!(matches!(
+            CompressionOptions::builder().level(42).build(),
+            Err(CompressionError::InvalidOption {
+                option: "level",
+                ..
+            })
+        ));
+        assert!(matches!(
+            CompressionOptions::builder().codec_name("nope").build(),
+            Err(CompressionError::InvalidOption {
+                option: "codec",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_errors_name_the_failed_stage() {
+        struct Failing;
+
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("bad sector"))
+            }
+        }
+
+        impl Write for Failing {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("disk full"))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let options = CompressionOptions::builder().build().unwrap();
+        assert!(matches!(
+            compress_stream_with_options(Failing, io::sink(), &options),
+            Err(CompressionError::Read { path: None, .. })
+        ));
+        assert!(matches!(
+            compress_stream_with_options(&b"data"[..], Failing, &options),
+            Err(CompressionError::Write { path: None, .. })
+        ));
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let reader = ProgressReader::new(&b"data"[..], None, cancel, |_| {});
+        assert!(matches!(
+            compress_stream_with_options(reader, io::sink(), &options),
+            Err(CompressionError::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn test_file_errors_carry_paths() {
+        let options = CompressionOptions::builder().build().unwrap();
+        let error = compress_file_with_options(
+            "test_options_missing.txt",
+            "test_options_missing.gz",
+            &options,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            &error,
+            CompressionError::Read { path: Some(path), .. }
+                if path == Path::new("test_options_missing.txt")
+        ));
+        assert!(error.to_string().contains("test_options_missing.txt"));
+        assert!(!Path::new("test_options_missing.gz").exists());
+    }
+}
@@ -0,0 +1,222 @@
+use crate::codec::{Codec, Gzip};
+use crate::{CompressionReport, CountingWriter};
+use flate2::{Compress, Compression, Crc, FlushCompress, Status};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::num::NonZeroUsize;
+use std::thread;
+
+/// Gzip header with no optional fields, unknown OS and no timestamp.
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+
+/// Settings for [`compress_stream_parallel`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelOptions {
+    /// Number of worker threads.
+    pub threads: usize,
+    /// Size of the uncompressed blocks handed to each worker.
+    pub block_size: usize,
+}
+
+impl Default for ParallelOptions {
+    fn default() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            block_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Raw-deflates one block. Blocks other than the last end on a sync flush,
+/// so they are byte aligned and can be concatenated into a single stream.
+pub(crate) fn deflate_block(data: &[u8], level: Compression, last: bool) -> io::Result<Vec<u8>> {
+    let mut compress = Compress::new(level, false);
+    let mut out = Vec::with_capacity(data.len() / 2 + 64);
+    let flush = if last {
+        FlushCompress::Finish
+    } else {
+        FlushCompress::Sync
+    };
+
+    loop {
+        if out.capacity() - out.len() < 64 {
+            out.reserve(out.capacity().max(4096));
+        }
+        let consumed = compress.total_in() as usize;
+        let status = compress
+            .compress_vec(&data[consumed..], &mut out, flush)
+            .map_err(io::Error::other)?;
+
+        let all_consumed = compress.total_in() as usize == data.len();
+        match status {
+            Status::StreamEnd => return Ok(out),
+            _ if !last && all_consumed && out.len() < out.capacity() => return Ok(out),
+            _ => {}
+        }
+    }
+}
+
+/// Reads up to `block_size` bytes, stopping early only at end of input.
+fn read_block<R: Read>(reader: &mut R, block_size: usize) -> io::Result<Vec<u8>> {
+    let mut block = Vec::with_capacity(block_size);
+    reader.take(block_size as u64).read_to_end(&mut block)?;
+    Ok(block)
+}
+
+/// Compresses `reader` into `writer` as a single gzip member, deflating
+/// blocks on `options.threads` threads at once.
+///
+/// The output is an ordinary gzip stream readable by `gunzip`. Each block
+/// is compressed without the previous block as a dictionary, so the ratio
+/// is slightly worse than [`crate::compress_stream`]. Memory use is bounded
+/// by roughly twice `threads * block_size`.
+pub fn compress_stream_parallel<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    compression_level: u32,
+    options: &ParallelOptions,
+) -> io::Result<CompressionReport> {
+    Gzip.validate_level(compression_level)?;
+    if options.threads == 0 || options.block_size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "thread count and block size must be greater than zero",
+        ));
+    }
+
+    let level = Compression::new(compression_level);
+    let mut output = CountingWriter::new(writer);
+    output.write_all(&GZIP_HEADER)?;
+
+    let mut crc = Crc::new();
+    let mut bytes_read = 0u64;
+    let mut next = read_block(&mut reader, options.block_size)?;
+
+    loop {
+        // Read one batch ahead by a block so we know which block is last
+        let mut batch = Vec::with_capacity(options.threads);
+        let mut last = false;
+        while batch.len() < options.threads {
+            let following = read_block(&mut reader, options.block_size)?;
+            last = following.is_empty();
+            batch.push(std::mem::replace(&mut next, following));
+            if last {
+                break;
+            }
+        }
+
+        let compressed = thread::scope(|scope| {
+            let batch_len = batch.len();
+            let handles: Vec<_> = batch
+                .iter()
+                .enumerate()
+                .map(|(i, block)| {
+                    let is_last = last && i + 1 == batch_len;
+                    scope.spawn(move || deflate_block(block, level, is_last))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("compression thread panicked"))
+                .collect::<io::Result<Vec<_>>>()
+        })?;
+
+        for (block, deflated) in batch.iter().zip(&compressed) {
+            let mut block_crc = Crc::new();
+            block_crc.update(block);
+            crc.combine(&block_crc);
+            bytes_read += block.len() as u64;
+            output.write_all(deflated)?;
+        }
+
+        if last {
+            break;
+        }
+    }
+
+    output.write_all(&crc.sum().to_le_bytes())?;
+    output.write_all(&(bytes_read as u32).to_le_bytes())?;
+    output.flush()?;
+
+    Ok(CompressionReport {
+        bytes_read,
+        bytes_written: output.count,
+    })
+}
+
+/// Like [`crate::compress_file`], compressing on several threads.
+pub fn compress_file_parallel(
+    input_file: &str,
+    output_file: &str,
+    compression_level: u32,
+    options: &ParallelOptions,
+) -> io::Result<CompressionReport> {
+    let input = BufReader::new(File::open(input_file)?);
+    let output = BufWriter::new(File::create(output_file)?);
+    compress_stream_parallel(input, output, compression_level, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (((i * 7) % 256) ^ (i / 1000)) as u8)
+            .collect()
+    }
+
+    fn parallel_gzip(data: &[u8], options: &ParallelOptions) -> io::Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        let report = compress_stream_parallel(data, &mut compressed, 6, options)?;
+        assert_eq!(report.bytes_read, data.len() as u64);
+        assert_eq!(report.bytes_written, compressed.len() as u64);
+        Ok(compressed)
+    }
+
+    #[test]
+    fn test_parallel_output_is_a_single_gzip_member() -> io::Result<()> {
+        let data = sample(100_000);
+        let options = ParallelOptions {
+            threads: 3,
+            block_size: 8 * 1024,
+        };
+
+        // GzDecoder stops after the first member, so this only passes if the
+        // blocks were stitched into one stream
+        let mut decoded = Vec::new();
+        GzDecoder::new(&parallel_gzip(&data, &options)?[..]).read_to_end(&mut decoded)?;
+        assert_eq!(decoded, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_handles_block_boundaries() -> io::Result<()> {
+        let options = ParallelOptions {
+            threads: 2,
+            block_size: 1024,
+        };
+
+        for len in [0, 1, 1023, 1024, 1025, 2048, 4096 + 1] {
+            let data = sample(len);
+            let mut decoded = Vec::new();
+            GzDecoder::new(&parallel_gzip(&data, &options)?[..]).read_to_end(&mut decoded)?;
+            assert_eq!(decoded, data, "input of {} bytes", len);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_rejects_invalid_options() {
+        let options = ParallelOptions {
+            threads: 0,
+            block_size: 1024,
+        };
+        let err = compress_stream_parallel(&b"data"[..], Vec::new(), 6, &options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
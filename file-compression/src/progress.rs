@@ -0,0 +1,284 @@
+use crate::codec::Codec;
+use crate::{compress_stream_with, CompressionReport};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Shared flag that asks a running job to stop.
+///
+/// Clones share the same flag, so one clone can be handed to the job and
+/// another kept to cancel it.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Error returned (wrapped in an `io::Error`) when a job was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Returns true if `error` means the job was cancelled.
+pub fn is_cancelled(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<Cancelled>())
+}
+
+/// How far a job has got.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Uncompressed bytes consumed so far.
+    pub bytes_processed: u64,
+    /// Size of the input, when known up front.
+    pub total_bytes: Option<u64>,
+}
+
+/// Reader wrapper that reports progress after every read and fails with
+/// [`Cancelled`] once its token is cancelled.
+pub struct ProgressReader<R, F> {
+    inner: R,
+    cancel: CancellationToken,
+    on_progress: F,
+    progress: Progress,
+}
+
+impl<R: Read, F: FnMut(Progress)> ProgressReader<R, F> {
+    pub fn new(
+        inner: R,
+        total_bytes: Option<u64>,
+        cancel: CancellationToken,
+        on_progress: F,
+    ) -> Self {
+        Self {
+            inner,
+            cancel,
+            on_progress,
+            progress: Progress {
+                bytes_processed: 0,
+                total_bytes,
+            },
+        }
+    }
+}
+
+impl<R: Read, F: FnMut(Progress)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.is_cancelled() {
+            return Err(io::Error::other(Cancelled));
+        }
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            self.progress.bytes_processed += n as u64;
+            (self.on_progress)(self.progress);
+        }
+        Ok(n)
+    }
+}
+
+/// Like [`crate::compress_file_with`], reporting progress through
+/// `on_progress` and stopping early once `cancel` is cancelled.
+///
+/// On any error, cancellation included, the output file is removed so no
+/// partial output is left behind.
+pub fn compress_file_with_progress<F: FnMut(Progress)>(
+    codec: &dyn Codec,
+    input_file: &str,
+    output_file: &str,
+    compression_level: u32,
+    cancel: &CancellationToken,
+    on_progress: F,
+) -> io::Result<CompressionReport> {
+    let input = File::open(input_file)?;
+    let total_bytes = input.metadata()?.len();
+    let input = BufReader::new(ProgressReader::new(
+        input,
+        Some(total_bytes),
+        cancel.clone(),
+        on_progress,
+    ));
+    let output = BufWriter::new(File::create(output_file)?);
+
+    let result = compress_stream_with(codec, input, output, compression_level);
+    if result.is_err() {
+        let _ = fs::remove_file(output_file);
+    }
+    result
+}
+
+/// Cancels its token when dropped unless disarmed, so an abandoned future
+/// stops the job it started.
+#[cfg(feature = "async")]
+struct CancelOnDrop(Option<CancellationToken>);
+
+#[cfg(feature = "async")]
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(token) = self.0.take() {
+            token.cancel();
+        }
+    }
+}
+
+/// Async version of [`compress_file_with_progress`] that runs on tokio's
+/// blocking pool, keeping runtime threads free.
+///
+/// `on_progress` is called on the blocking thread, so it should be cheap;
+/// sending into a channel such as `tokio::sync::watch` works well. Dropping
+/// the returned future cancels the job.
+#[cfg(feature = "async")]
+pub async fn compress_file_async<C, F>(
+    codec: C,
+    input_file: String,
+    output_file: String,
+    compression_level: u32,
+    cancel: CancellationToken,
+    on_progress: F,
+) -> io::Result<CompressionReport>
+where
+    C: Codec + Send + 'static,
+    F: FnMut(Progress) + Send + 'static,
+{
+    let mut guard = CancelOnDrop(Some(cancel.clone()));
+    let result = tokio::task::spawn_blocking(move || {
+        compress_file_with_progress(
+            &codec,
+            &input_file,
+            &output_file,
+            compression_level,
+            &cancel,
+            on_progress,
+        )
+    })
+    .await;
+    guard.0 = None;
+
+    result.map_err(io::Error::other)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Gzip;
+    use std::path::Path;
+
+    #[test]
+    fn test_progress_reaches_total() -> io::Result<()> {
+        let input_path = "test_progress_input.txt";
+        let output_path = "test_progress_output.gz";
+        fs::write(input_path, b"progress ".repeat(50_000))?;
+
+        let mut updates = Vec::new();
+        let report = compress_file_with_progress(
+            &Gzip,
+            input_path,
+            output_path,
+            6,
+            &CancellationToken::new(),
+            |progress| updates.push(progress),
+        )?;
+
+        assert!(updates.len() > 1);
+        assert!(updates
+            .windows(2)
+            .all(|pair| pair[0].bytes_processed < pair[1].bytes_processed));
+        let last = updates.last().unwrap();
+        assert_eq!(last.bytes_processed, report.bytes_read);
+        assert_eq!(last.total_bytes, Some(report.bytes_read));
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+        fs::remove_file(output_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancelled_job_leaves_no_output() -> io::Result<()> {
+        let input_path = "test_progress_cancel_input.txt";
+        let output_path = "test_progress_cancel_output.gz";
+        fs::write(input_path, b"cancel me ".repeat(100_000))?;
+
+        // Cancel part way through, from inside the progress callback
+        let cancel = CancellationToken::new();
+        let err = compress_file_with_progress(&Gzip, input_path, output_path, 6, &cancel, {
+            let cancel = cancel.clone();
+            move |progress| {
+                if progress.bytes_processed > 100_000 {
+                    cancel.cancel();
+                }
+            }
+        })
+        .unwrap_err();
+
+        assert!(is_cancelled(&err));
+        assert!(!Path::new(output_path).exists());
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+
+        Ok(())
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_async_compression_runs_and_cancels() -> io::Result<()> {
+        let input_path = "test_progress_async_input.txt";
+        let output_path = "test_progress_async_output.gz";
+        fs::write(input_path, b"async ".repeat(100_000))?;
+
+        let (sender, receiver) = tokio::sync::watch::channel(Progress::default());
+        let report = compress_file_async(
+            &Gzip,
+            input_path.to_string(),
+            output_path.to_string(),
+            6,
+            CancellationToken::new(),
+            move |progress| {
+                sender.send_replace(progress);
+            },
+        )
+        .await?;
+        assert_eq!(receiver.borrow().bytes_processed, report.bytes_read);
+
+        // A token cancelled up front stops the job before any output is kept
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let err = compress_file_async(
+            &Gzip,
+            input_path.to_string(),
+            output_path.to_string(),
+            6,
+            cancel,
+            |_| {},
+        )
+        .await
+        .unwrap_err();
+        assert!(is_cancelled(&err));
+        assert!(!Path::new(output_path).exists());
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+
+        Ok(())
+    }
+}
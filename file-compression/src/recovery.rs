@@ -0,0 +1,579 @@
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+/// Bytes every recovery record starts with.
+pub const RECOVERY_MAGIC: [u8; 4] = *b"FCRR";
+
+/// Recovery record layout version written by this library.
+const RECOVERY_VERSION: u8 = 1;
+
+/// Size of the magic, version, block size, data shards, redundancy and
+/// file length.
+const PREAMBLE_SIZE: u64 = 20;
+
+const DIGEST_SIZE: usize = 32;
+
+/// Reed-Solomon over GF(2^8) supports at most this many blocks per stripe,
+/// data and parity together.
+const MAX_SHARDS: usize = 256;
+
+type BlockDigest = [u8; DIGEST_SIZE];
+
+/// Settings for [`write_recovery_record`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecoveryOptions {
+    /// Parity blocks per stripe as a percentage of its data blocks, rounded
+    /// up. 10 means any 10% of a stripe's blocks can be lost and rebuilt.
+    pub redundancy_percent: u8,
+    /// Size of the blocks the file is split into. Damage is repaired a
+    /// whole block at a time.
+    pub block_size: usize,
+    /// Data blocks per stripe. Each stripe has its own parity blocks, so
+    /// damage in one stripe cannot use up another stripe's redundancy.
+    pub data_shards: usize,
+}
+
+impl Default for RecoveryOptions {
+    fn default() -> Self {
+        Self {
+            redundancy_percent: 10,
+            block_size: 64 * 1024,
+            data_shards: 100,
+        }
+    }
+}
+
+impl RecoveryOptions {
+    fn validate(&self) -> io::Result<()> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        if !(1..=100).contains(&self.redundancy_percent) {
+            return Err(invalid(format!(
+                "redundancy must be between 1 and 100 percent, got {}",
+                self.redundancy_percent
+            )));
+        }
+        if self.block_size == 0 || u32::try_from(self.block_size).is_err() {
+            return Err(invalid(format!("invalid block size {}", self.block_size)));
+        }
+        let shards = self.data_shards + parity_shards(self.data_shards, self.redundancy_percent);
+        if self.data_shards == 0 || shards > MAX_SHARDS {
+            return Err(invalid(format!(
+                "{} data blocks per stripe need {} blocks with parity, at most {} are supported",
+                self.data_shards, shards, MAX_SHARDS
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Parity blocks protecting a stripe of `data_shards` blocks.
+fn parity_shards(data_shards: usize, redundancy_percent: u8) -> usize {
+    (data_shards * redundancy_percent as usize)
+        .div_ceil(100)
+        .max(1)
+}
+
+/// One stripe: a run of data blocks and the parity blocks covering them.
+#[derive(Debug, Clone, Copy)]
+struct Stripe {
+    first_block: u64,
+    data_shards: usize,
+    parity_shards: usize,
+}
+
+/// How a file of `file_len` bytes is split into blocks and stripes.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    options: RecoveryOptions,
+    file_len: u64,
+}
+
+impl Layout {
+    fn block_count(&self) -> u64 {
+        self.file_len.div_ceil(self.options.block_size as u64)
+    }
+
+    /// Size of block `index`; only the last one can be short.
+    fn block_len(&self, index: u64) -> usize {
+        let start = index * self.options.block_size as u64;
+        (self.file_len - start).min(self.options.block_size as u64) as usize
+    }
+
+    /// Stripes in file order. The last one holds the remaining blocks and
+    /// gets parity in proportion to them.
+    fn stripes(&self) -> impl Iterator<Item = Stripe> {
+        let blocks = self.block_count();
+        let per_stripe = self.options.data_shards as u64;
+        let redundancy = self.options.redundancy_percent;
+        (0..blocks.div_ceil(per_stripe)).map(move |stripe| {
+            let first_block = stripe * per_stripe;
+            let data_shards = (blocks - first_block).min(per_stripe) as usize;
+            Stripe {
+                first_block,
+                data_shards,
+                parity_shards: parity_shards(data_shards, redundancy),
+            }
+        })
+    }
+
+    fn parity_count(&self) -> u64 {
+        self.stripes()
+            .map(|stripe| stripe.parity_shards as u64)
+            .sum()
+    }
+
+    /// Size of the preamble, block digests and metadata checksum, which
+    /// come before the parity blocks.
+    fn metadata_len(&self) -> Option<u64> {
+        let digests = self.block_count().checked_add(self.parity_count())?;
+        digests
+            .checked_mul(DIGEST_SIZE as u64)?
+            .checked_add(PREAMBLE_SIZE + DIGEST_SIZE as u64)
+    }
+}
+
+/// Everything in a recovery record except the parity blocks.
+///
+/// On disk: the magic, a version byte, the block size (`u32`), data blocks
+/// per stripe (`u16`), redundancy percent (`u8`) and protected file length
+/// (`u64`), all little-endian. Then the SHA-256 of every data block, of
+/// every parity block, and of all the preceding bytes. The parity blocks
+/// follow, stripe by stripe, each a full block long.
+struct RecoveryRecord {
+    layout: Layout,
+    data_digests: Vec<BlockDigest>,
+    parity_digests: Vec<BlockDigest>,
+}
+
+impl RecoveryRecord {
+    fn to_bytes(&self) -> Vec<u8> {
+        let options = &self.layout.options;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&RECOVERY_MAGIC);
+        bytes.push(RECOVERY_VERSION);
+        bytes.extend_from_slice(&(options.block_size as u32).to_le_bytes());
+        bytes.extend_from_slice(&(options.data_shards as u16).to_le_bytes());
+        bytes.push(options.redundancy_percent);
+        bytes.extend_from_slice(&self.layout.file_len.to_le_bytes());
+        for digest in self.data_digests.iter().chain(&self.parity_digests) {
+            bytes.extend_from_slice(digest);
+        }
+        let checksum = Sha256::digest(&bytes);
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    /// Reads the metadata of a record that is `record_len` bytes long,
+    /// leaving `reader` at the first parity block.
+    fn read_from<R: Read>(reader: &mut R, record_len: u64) -> io::Result<Self> {
+        let damaged = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut preamble = [0u8; PREAMBLE_SIZE as usize];
+        reader.read_exact(&mut preamble)?;
+        if preamble[..4] != RECOVERY_MAGIC {
+            return Err(damaged("not a recovery record"));
+        }
+        if preamble[4] != RECOVERY_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("unsupported recovery record version {}", preamble[4]),
+            ));
+        }
+        let layout = Layout {
+            options: RecoveryOptions {
+                block_size: u32::from_le_bytes(preamble[5..9].try_into().unwrap()) as usize,
+                data_shards: u16::from_le_bytes(preamble[9..11].try_into().unwrap()) as usize,
+                redundancy_percent: preamble[11],
+            },
+            file_len: u64::from_le_bytes(preamble[12..20].try_into().unwrap()),
+        };
+        layout
+            .options
+            .validate()
+            .map_err(|_| damaged("recovery record settings are damaged"))?;
+        // Checked against the actual length before allocating anything
+        match layout.metadata_len() {
+            Some(len) if len <= record_len => {}
+            _ => return Err(damaged("recovery record is truncated")),
+        }
+
+        let mut read_digests = |count: u64| -> io::Result<Vec<BlockDigest>> {
+            let mut digests = vec![[0u8; DIGEST_SIZE]; count as usize];
+            for digest in &mut digests {
+                reader.read_exact(digest)?;
+            }
+            Ok(digests)
+        };
+        let record = Self {
+            layout,
+            data_digests: read_digests(layout.block_count())?,
+            parity_digests: read_digests(layout.parity_count())?,
+        };
+
+        let mut checksum = [0u8; DIGEST_SIZE];
+        reader.read_exact(&mut checksum)?;
+        let bytes = record.to_bytes();
+        if bytes[bytes.len() - DIGEST_SIZE..] != checksum {
+            return Err(damaged("recovery record metadata is damaged"));
+        }
+        Ok(record)
+    }
+}
+
+/// What [`write_recovery_record`] wrote.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryReport {
+    pub data_blocks: u64,
+    pub parity_blocks: u64,
+    /// Size of the recovery record.
+    pub bytes_written: u64,
+}
+
+/// What [`repair_file`] found and fixed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Indices of the data blocks that were damaged and rebuilt.
+    pub repaired_blocks: Vec<u64>,
+    /// Parity blocks in the recovery record that were damaged. They are
+    /// not rewritten; run [`write_recovery_record`] again once the file is
+    /// known to be good.
+    pub damaged_parity_blocks: u64,
+}
+
+/// Path of the recovery record for `file`.
+pub fn recovery_path(file: &str) -> String {
+    format!("{}.rec", file)
+}
+
+fn reed_solomon(stripe: &Stripe) -> io::Result<ReedSolomon> {
+    ReedSolomon::new(stripe.data_shards, stripe.parity_shards).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid stripe: {:?}", e),
+        )
+    })
+}
+
+fn block_digest(data: &[u8]) -> BlockDigest {
+    Sha256::digest(data).into()
+}
+
+/// Reads until `buf` is full or the input ends, returning the bytes read.
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Writes Reed-Solomon parity for `file` to [`recovery_path`]`(file)`.
+///
+/// The file is split into blocks of `options.block_size`, grouped into
+/// stripes of `options.data_shards` blocks, and each stripe gets
+/// `options.redundancy_percent` percent parity blocks. [`repair_file`] can
+/// then rebuild up to that many damaged blocks per stripe. The record is
+/// removed if writing it fails.
+pub fn write_recovery_record(file: &str, options: &RecoveryOptions) -> io::Result<RecoveryReport> {
+    options.validate()?;
+    let input = File::open(file)?;
+    let layout = Layout {
+        options: *options,
+        file_len: input.metadata()?.len(),
+    };
+
+    let record_path = recovery_path(file);
+    let output = File::create(&record_path)?;
+    write_record(BufReader::new(input), output, layout).inspect_err(|_| {
+        let _ = fs::remove_file(&record_path);
+    })
+}
+
+fn write_record<R: Read>(mut input: R, output: File, layout: Layout) -> io::Result<RecoveryReport> {
+    let block_size = layout.options.block_size;
+    let metadata_len = layout.metadata_len().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "file is too large to protect")
+    })?;
+
+    // The digests go before the parity, so their space is reserved and
+    // filled in once every block has been read
+    let mut output = BufWriter::new(output);
+    output.write_all(&vec![0u8; metadata_len as usize])?;
+
+    let mut data_digests = Vec::new();
+    let mut parity_digests = Vec::new();
+    for stripe in layout.stripes() {
+        let mut shards = vec![vec![0u8; block_size]; stripe.data_shards + stripe.parity_shards];
+        for (i, shard) in shards[..stripe.data_shards].iter_mut().enumerate() {
+            // A short last block is padded with zeros for the parity
+            let len = layout.block_len(stripe.first_block + i as u64);
+            input.read_exact(&mut shard[..len])?;
+            data_digests.push(block_digest(&shard[..len]));
+        }
+
+        reed_solomon(&stripe)?
+            .encode(&mut shards)
+            .map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        for shard in &shards[stripe.data_shards..] {
+            parity_digests.push(block_digest(shard));
+            output.write_all(shard)?;
+        }
+    }
+
+    let record = RecoveryRecord {
+        layout,
+        data_digests,
+        parity_digests,
+    };
+    let mut output = output
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    output.seek(SeekFrom::Start(0))?;
+    output.write_all(&record.to_bytes())?;
+    output.sync_all()?;
+
+    let parity_blocks = record.parity_digests.len() as u64;
+    Ok(RecoveryReport {
+        data_blocks: record.data_digests.len() as u64,
+        parity_blocks,
+        bytes_written: metadata_len + parity_blocks * block_size as u64,
+    })
+}
+
+/// Checks `file` against its recovery record and rebuilds damaged blocks
+/// in place, including blocks lost to truncation. Bytes appended past the
+/// protected length are cut off.
+///
+/// Stripes with more damaged blocks than parity are left as they are, and
+/// the function fails with `InvalidData` once every other stripe has been
+/// repaired.
+pub fn repair_file(file: &str) -> io::Result<RepairReport> {
+    let record_file = File::open(recovery_path(file))?;
+    let record_len = record_file.metadata()?.len();
+    let mut record_reader = BufReader::new(record_file);
+    let record = RecoveryRecord::read_from(&mut record_reader, record_len)?;
+    let layout = record.layout;
+    let block_size = layout.options.block_size;
+
+    let mut target = OpenOptions::new().read(true).write(true).open(file)?;
+    let mut report = RepairReport::default();
+    let mut lost_blocks = 0;
+    let mut parity_index = 0;
+
+    for stripe in layout.stripes() {
+        let mut shards: Vec<Option<Vec<u8>>> =
+            Vec::with_capacity(stripe.data_shards + stripe.parity_shards);
+        let mut damaged = Vec::new();
+
+        target.seek(SeekFrom::Start(stripe.first_block * block_size as u64))?;
+        for i in 0..stripe.data_shards {
+            let index = stripe.first_block + i as u64;
+            let len = layout.block_len(index);
+            let mut shard = vec![0u8; block_size];
+            let read = read_up_to(&mut target, &mut shard[..len])?;
+            if read == len && block_digest(&shard[..len]) == record.data_digests[index as usize] {
+                shards.push(Some(shard));
+            } else {
+                damaged.push(index);
+                shards.push(None);
+            }
+        }
+
+        // Parity blocks are checked too, so a damaged one is never used
+        for _ in 0..stripe.parity_shards {
+            let mut shard = vec![0u8; block_size];
+            let read = read_up_to(&mut record_reader, &mut shard)?;
+            if read == block_size && block_digest(&shard) == record.parity_digests[parity_index] {
+                shards.push(Some(shard));
+            } else {
+                report.damaged_parity_blocks += 1;
+                shards.push(None);
+            }
+            parity_index += 1;
+        }
+
+        if damaged.is_empty() {
+            continue;
+        }
+        let missing = shards.iter().filter(|shard| shard.is_none()).count();
+        if missing > stripe.parity_shards {
+            lost_blocks += damaged.len();
+            continue;
+        }
+
+        reed_solomon(&stripe)?
+            .reconstruct_data(&mut shards)
+            .map_err(|e| io::Error::other(format!("{:?}", e)))?;
+        for &index in &damaged {
+            let shard = shards[(index - stripe.first_block) as usize]
+                .as_ref()
+                .expect("data block was reconstructed");
+            target.seek(SeekFrom::Start(index * block_size as u64))?;
+            target.write_all(&shard[..layout.block_len(index)])?;
+        }
+        report.repaired_blocks.extend(damaged);
+    }
+
+    if target.metadata()?.len() != layout.file_len {
+        target.set_len(layout.file_len)?;
+    }
+    target.sync_all()?;
+
+    if lost_blocks > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} damaged blocks of {} could not be repaired ({} were)",
+                lost_blocks,
+                file,
+                report.repaired_blocks.len()
+            ),
+        ));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Gzip;
+    use crate::{compress_file_with, decompress_file_with, DecompressionLimits};
+
+    fn options() -> RecoveryOptions {
+        RecoveryOptions {
+            redundancy_percent: 20,
+            block_size: 1024,
+            data_shards: 10,
+        }
+    }
+
+    fn sample(len: usize) -> Vec<u8> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_repair_restores_damaged_gzip() -> io::Result<()> {
+        let input_path = "test_recovery_input.bin";
+        let output_path = "test_recovery_input.bin.gz";
+        let restored_path = "test_recovery_restored.bin";
+        let data = sample(30_000);
+        fs::write(input_path, &data)?;
+        compress_file_with(&Gzip, input_path, output_path, 6)?;
+        let original = fs::read(output_path)?;
+
+        let report = write_recovery_record(output_path, &options())?;
+        assert_eq!(report.data_blocks, original.len().div_ceil(1024) as u64);
+        assert_eq!(
+            report.bytes_written,
+            fs::metadata(recovery_path(output_path))?.len()
+        );
+        assert_eq!(repair_file(output_path)?, RepairReport::default());
+
+        // Flip a bit in one block and overwrite another in a second stripe
+        let mut damaged = original.clone();
+        damaged[100] ^= 0x01;
+        damaged[12 * 1024..12 * 1024 + 300].fill(0);
+        fs::write(output_path, &damaged)?;
+        assert!(decompress_file_with(
+            &Gzip,
+            output_path,
+            restored_path,
+            &DecompressionLimits::default()
+        )
+        .is_err());
+
+        let report = repair_file(output_path)?;
+        assert_eq!(report.repaired_blocks, vec![0, 12]);
+        assert_eq!(fs::read(output_path)?, original);
+        decompress_file_with(
+            &Gzip,
+            output_path,
+            restored_path,
+            &DecompressionLimits::default(),
+        )?;
+        assert_eq!(fs::read(restored_path)?, data);
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+        fs::remove_file(output_path)?;
+        fs::remove_file(recovery_path(output_path))?;
+        fs::remove_file(restored_path)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repair_truncated_file_and_damaged_parity() -> io::Result<()> {
+        let path = "test_recovery_truncated.bin";
+        let original = sample(9 * 1024 + 100);
+        fs::write(path, &original)?;
+        let options = RecoveryOptions {
+            redundancy_percent: 30,
+            ..options()
+        };
+        assert_eq!(write_recovery_record(path, &options)?.parity_blocks, 3);
+
+        // Damage the first parity block and lose the file's last two blocks
+        let mut record = fs::read(recovery_path(path))?;
+        let record_len = record.len();
+        record[record_len - 3 * 1024 + 10] ^= 0xff;
+        fs::write(recovery_path(path), &record)?;
+        fs::write(path, &original[..8 * 1024 + 500])?;
+
+        let report = repair_file(path)?;
+        assert_eq!(report.repaired_blocks, vec![8, 9]);
+        assert_eq!(report.damaged_parity_blocks, 1);
+        assert_eq!(fs::read(path)?, original);
+
+        // Cleanup
+        fs::remove_file(path)?;
+        fs::remove_file(recovery_path(path))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_too_much_damage_is_reported() -> io::Result<()> {
+        let path = "test_recovery_too_much.bin";
+        let original = sample(10 * 1024);
+        fs::write(path, &original)?;
+        write_recovery_record(path, &options())?;
+
+        // Two parity blocks cannot rebuild three lost blocks
+        let mut damaged = original.clone();
+        damaged[..3 * 1024].fill(0xaa);
+        fs::write(path, &damaged)?;
+        let err = repair_file(path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let invalid = RecoveryOptions {
+            redundancy_percent: 0,
+            ..options()
+        };
+        assert_eq!(
+            write_recovery_record(path, &invalid).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+
+        // Cleanup
+        fs::remove_file(path)?;
+        fs::remove_file(recovery_path(path))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,467 @@
+use crate::codec::{Codec, Gzip};
+use crate::parallel::deflate_block;
+use crate::{CompressionReport, CountingWriter};
+use flate2::read::GzDecoder;
+use flate2::{Compression, Crc};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+/// Uncompressed bytes per block. BGZF uses 0xff00 so that even a stored
+/// (uncompressible) block fits the 64 KiB block limit.
+pub const BGZF_BLOCK_SIZE: usize = 0xff00;
+
+/// Largest allowed block, header and trailer included.
+const MAX_BLOCK_SIZE: usize = 64 * 1024;
+
+/// Size of the block header, ending with the `BC` extra subfield that
+/// records the block size.
+const HEADER_SIZE: usize = 18;
+
+/// CRC-32 and ISIZE.
+const TRAILER_SIZE: usize = 8;
+
+/// Empty block that marks the end of a BGZF file.
+const EOF_MARKER: [u8; 28] = [
+    0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0, 0x1b, 0, 3, 0, 0, 0, 0, 0, 0, 0,
+    0, 0,
+];
+
+/// Start of a block in the compressed and uncompressed streams.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockOffset {
+    pub compressed: u64,
+    pub uncompressed: u64,
+}
+
+/// Maps uncompressed offsets to the compressed blocks holding them.
+///
+/// The first block always starts at offset zero in both streams.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeekIndex {
+    blocks: Vec<BlockOffset>,
+}
+
+impl SeekIndex {
+    /// Every block in file order.
+    pub fn blocks(&self) -> &[BlockOffset] {
+        &self.blocks
+    }
+
+    /// Writes the index in the `.gzi` layout used by `bgzip`: a little-endian
+    /// `u64` count followed by `(compressed, uncompressed)` pairs for every
+    /// block but the first.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let rest = &self.blocks[1..];
+        writer.write_all(&(rest.len() as u64).to_le_bytes())?;
+        for block in rest {
+            writer.write_all(&block.compressed.to_le_bytes())?;
+            writer.write_all(&block.uncompressed.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    /// Reads an index written by [`SeekIndex::write_to`]. Fails with
+    /// `InvalidData` if offsets are not increasing.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let count = read_u64(&mut reader)?;
+        let mut blocks = vec![BlockOffset {
+            compressed: 0,
+            uncompressed: 0,
+        }];
+
+        // Not preallocated, as the count comes from the file
+        for _ in 0..count {
+            let block = BlockOffset {
+                compressed: read_u64(&mut reader)?,
+                uncompressed: read_u64(&mut reader)?,
+            };
+            let previous = blocks[blocks.len() - 1];
+            if block.compressed <= previous.compressed
+                || block.uncompressed <= previous.uncompressed
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "seek index offsets are not increasing",
+                ));
+            }
+            blocks.push(block);
+        }
+
+        Ok(Self { blocks })
+    }
+
+    /// Position in [`SeekIndex::blocks`] of the block holding `offset`.
+    fn block_for(&self, offset: u64) -> usize {
+        self.blocks
+            .partition_point(|block| block.uncompressed <= offset)
+            .saturating_sub(1)
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Writes `data` as one BGZF block.
+fn write_block<W: Write>(writer: &mut W, data: &[u8], level: Compression) -> io::Result<()> {
+    let mut deflated = deflate_block(data, level, true)?;
+    if HEADER_SIZE + deflated.len() + TRAILER_SIZE > MAX_BLOCK_SIZE {
+        deflated = deflate_block(data, Compression::none(), true)?;
+    }
+
+    let block_size = HEADER_SIZE + deflated.len() + TRAILER_SIZE;
+    let mut header = [
+        0x1f, 0x8b, 8, 4, 0, 0, 0, 0, 0, 0xff, 6, 0, b'B', b'C', 2, 0, 0, 0,
+    ];
+    header[16..].copy_from_slice(&((block_size - 1) as u16).to_le_bytes());
+
+    let mut crc = Crc::new();
+    crc.update(data);
+
+    writer.write_all(&header)?;
+    writer.write_all(&deflated)?;
+    writer.write_all(&crc.sum().to_le_bytes())?;
+    writer.write_all(&(data.len() as u32).to_le_bytes())
+}
+
+/// Compresses `reader` into `writer` as BGZF: a series of independent gzip
+/// members of at most [`BGZF_BLOCK_SIZE`] uncompressed bytes each, followed
+/// by an empty end-of-file block.
+///
+/// The output is ordinary multi-member gzip, so `gunzip` reads it as usual.
+/// The returned index allows [`SeekableReader`] to decompress only the
+/// blocks a read touches.
+pub fn compress_stream_seekable<R: Read, W: Write>(
+    mut reader: R,
+    writer: W,
+    compression_level: u32,
+) -> io::Result<(CompressionReport, SeekIndex)> {
+    Gzip.validate_level(compression_level)?;
+    let level = Compression::new(compression_level);
+    let mut output = CountingWriter::new(writer);
+    let mut blocks = Vec::new();
+    let mut bytes_read = 0u64;
+    let mut block = Vec::with_capacity(BGZF_BLOCK_SIZE);
+
+    loop {
+        block.clear();
+        (&mut reader)
+            .take(BGZF_BLOCK_SIZE as u64)
+            .read_to_end(&mut block)?;
+        if block.is_empty() && !blocks.is_empty() {
+            break;
+        }
+
+        blocks.push(BlockOffset {
+            compressed: output.count,
+            uncompressed: bytes_read,
+        });
+        // Empty input still gets a first block so the index is never empty
+        if block.is_empty() {
+            break;
+        }
+        write_block(&mut output, &block, level)?;
+        bytes_read += block.len() as u64;
+    }
+
+    output.write_all(&EOF_MARKER)?;
+    output.flush()?;
+
+    Ok((
+        CompressionReport {
+            bytes_read,
+            bytes_written: output.count,
+        },
+        SeekIndex { blocks },
+    ))
+}
+
+/// Path of the index stored next to a seekable file.
+pub fn index_path(compressed_file: &str) -> String {
+    format!("{}.gzi", compressed_file)
+}
+
+/// Like [`crate::compress_file`], writing BGZF to `output_file` and its
+/// index to [`index_path`]`(output_file)`.
+pub fn compress_file_seekable(
+    input_file: &str,
+    output_file: &str,
+    compression_level: u32,
+) -> io::Result<(CompressionReport, SeekIndex)> {
+    let input = BufReader::new(File::open(input_file)?);
+    let output = BufWriter::new(File::create(output_file)?);
+    let (report, index) = compress_stream_seekable(input, output, compression_level)?;
+    index.write_to(BufWriter::new(File::create(index_path(output_file))?))?;
+    Ok((report, index))
+}
+
+/// Random-access reader over BGZF data, in uncompressed offsets.
+///
+/// Only the block holding the current position is decompressed, and it is
+/// cached until a read moves past it.
+pub struct SeekableReader<R> {
+    inner: R,
+    index: SeekIndex,
+    /// Compressed offset of the end-of-file marker.
+    data_end: u64,
+    len: u64,
+    position: u64,
+    block: Vec<u8>,
+    block_number: Option<usize>,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    /// Wraps BGZF data with its index. Fails with `InvalidData` if the data
+    /// does not end with the end-of-file block or does not match the index.
+    pub fn new(mut inner: R, index: SeekIndex) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let data_end = inner
+            .seek(SeekFrom::End(0))?
+            .checked_sub(EOF_MARKER.len() as u64)
+            .ok_or_else(|| invalid("input is too short to be BGZF"))?;
+        let mut marker = [0u8; EOF_MARKER.len()];
+        inner.seek(SeekFrom::Start(data_end))?;
+        inner.read_exact(&mut marker)?;
+        if marker != EOF_MARKER {
+            return Err(invalid("missing BGZF end-of-file block"));
+        }
+
+        let last = index.blocks[index.blocks.len() - 1];
+        let len = if last.compressed == data_end {
+            last.uncompressed
+        } else if last.compressed + (HEADER_SIZE + TRAILER_SIZE) as u64 <= data_end {
+            // The last block's ISIZE sits right before the marker
+            let mut size = [0u8; 4];
+            inner.seek(SeekFrom::Start(data_end - 4))?;
+            inner.read_exact(&mut size)?;
+            last.uncompressed + u32::from_le_bytes(size) as u64
+        } else {
+            return Err(invalid("seek index points past the end of the data"));
+        };
+
+        Ok(Self {
+            inner,
+            index,
+            data_end,
+            len,
+            position: 0,
+            block: Vec::new(),
+            block_number: None,
+        })
+    }
+
+    /// Size of the uncompressed data.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads up to `len` uncompressed bytes starting at `offset`. The result
+    /// is shorter only if the range runs past the end of the data.
+    pub fn read_range(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        self.seek(SeekFrom::Start(offset))?;
+        let mut range = Vec::new();
+        Read::by_ref(self).take(len).read_to_end(&mut range)?;
+        Ok(range)
+    }
+
+    fn load_block(&mut self, number: usize) -> io::Result<()> {
+        let blocks = self.index.blocks();
+        let start = blocks[number];
+        let (compressed_end, uncompressed_end) = match blocks.get(number + 1) {
+            Some(next) => (next.compressed, next.uncompressed),
+            None => (self.data_end, self.len),
+        };
+
+        self.block_number = None;
+        self.block.clear();
+        self.inner.seek(SeekFrom::Start(start.compressed))?;
+        GzDecoder::new((&mut self.inner).take(compressed_end - start.compressed))
+            .read_to_end(&mut self.block)?;
+
+        let expected = uncompressed_end - start.uncompressed;
+        if self.block.len() as u64 != expected {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "block {} decompressed to {} bytes, index expects {}",
+                    number,
+                    self.block.len(),
+                    expected
+                ),
+            ));
+        }
+        self.block_number = Some(number);
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let number = self.index.block_for(self.position);
+        if self.block_number != Some(number) {
+            self.load_block(number)?;
+        }
+
+        let offset = (self.position - self.index.blocks()[number].uncompressed) as usize;
+        let n = buf.len().min(self.block.len() - offset);
+        buf[..n].copy_from_slice(&self.block[offset..offset + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Reads an uncompressed byte range from a file written by
+/// [`compress_file_seekable`], using the index stored next to it.
+pub fn read_file_range(compressed_file: &str, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+    let index = SeekIndex::read_from(BufReader::new(File::open(index_path(compressed_file))?))?;
+    let mut reader = SeekableReader::new(BufReader::new(File::open(compressed_file)?), index)?;
+    reader.read_range(offset, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::MultiGzDecoder;
+    use std::fs;
+    use std::io::Cursor;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (((i * 31) % 251) ^ (i / 4096)) as u8)
+            .collect()
+    }
+
+    fn seekable(data: &[u8]) -> io::Result<(Vec<u8>, SeekIndex)> {
+        let mut compressed = Vec::new();
+        let (report, index) = compress_stream_seekable(data, &mut compressed, 6)?;
+        assert_eq!(report.bytes_read, data.len() as u64);
+        assert_eq!(report.bytes_written, compressed.len() as u64);
+        Ok((compressed, index))
+    }
+
+    #[test]
+    fn test_output_is_valid_multi_member_gzip() -> io::Result<()> {
+        let data = sample(BGZF_BLOCK_SIZE * 3 + 100);
+        let (compressed, index) = seekable(&data)?;
+        assert_eq!(index.blocks().len(), 4);
+        assert!(compressed.ends_with(&EOF_MARKER));
+
+        let mut decoded = Vec::new();
+        MultiGzDecoder::new(&compressed[..]).read_to_end(&mut decoded)?;
+        assert_eq!(decoded, data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_ranges_across_blocks() -> io::Result<()> {
+        let data = sample(BGZF_BLOCK_SIZE * 4 + 1234);
+        let (compressed, index) = seekable(&data)?;
+        let mut reader = SeekableReader::new(Cursor::new(compressed), index)?;
+        assert_eq!(reader.len(), data.len() as u64);
+
+        let block = BGZF_BLOCK_SIZE as u64;
+        for (offset, len) in [
+            (0, 10),
+            (block - 5, 10),
+            (block * 2, block + 1),
+            (12_345, block * 3),
+            (data.len() as u64 - 3, 100),
+            (data.len() as u64 + 10, 5),
+        ] {
+            let start = (offset as usize).min(data.len());
+            let end = ((offset + len) as usize).min(data.len());
+            assert_eq!(
+                reader.read_range(offset, len)?,
+                &data[start..end],
+                "range {}+{}",
+                offset,
+                len
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_incompressible_blocks_fit() -> io::Result<()> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let data: Vec<u8> = (0..BGZF_BLOCK_SIZE * 2)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 32) as u8
+            })
+            .collect();
+        let (compressed, index) = seekable(&data)?;
+
+        let mut reader = SeekableReader::new(Cursor::new(compressed), index)?;
+        assert_eq!(reader.read_range(100, 70_000)?, &data[100..70_100]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_input() -> io::Result<()> {
+        let (compressed, index) = seekable(b"")?;
+        assert_eq!(compressed, EOF_MARKER);
+
+        let mut reader = SeekableReader::new(Cursor::new(compressed), index)?;
+        assert!(reader.is_empty());
+        assert!(reader.read_range(0, 10)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_round_trip_with_stored_index() -> io::Result<()> {
+        let input_path = "test_seekable_input.log";
+        let output_path = "test_seekable_output.gz";
+        let data = sample(BGZF_BLOCK_SIZE * 2 + 77);
+        fs::write(input_path, &data)?;
+
+        let (_, index) = compress_file_seekable(input_path, output_path, 6)?;
+        let stored = SeekIndex::read_from(File::open(index_path(output_path))?)?;
+        assert_eq!(stored, index);
+        assert_eq!(
+            read_file_range(output_path, 65_000, 2_000)?,
+            &data[65_000..67_000]
+        );
+
+        // Cleanup
+        fs::remove_file(input_path)?;
+        fs::remove_file(output_path)?;
+        fs::remove_file(index_path(output_path))?;
+
+        Ok(())
+    }
+}
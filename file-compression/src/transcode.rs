@@ -0,0 +1,152 @@
+use crate::checksum::{compress_stream_checked, ChecksumReport};
+use crate::codec::Codec;
+use crate::decompress::{DecompressionLimits, LimitedDecoder};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+/// Result of a transcode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscodeReport {
+    /// Compressed bytes read from the source.
+    pub source_bytes: u64,
+    /// Sizes and SHA-256 digests of the decompressed data (`bytes_read`,
+    /// `original_sha256`) and of the new output (`bytes_written`,
+    /// `compressed_sha256`).
+    pub output: ChecksumReport,
+}
+
+/// Recompresses a stream from one codec to another in a single pass.
+///
+/// The source is decoded and fed straight into the new encoder, so memory
+/// use stays bounded and nothing is written to disk in between. `limits`
+/// guards the decoding side like it does for decompression; the digest of
+/// the decoded data is returned so callers can confirm nothing changed.
+pub fn transcode_stream<R: Read, W: Write>(
+    from: &dyn Codec,
+    to: &dyn Codec,
+    reader: R,
+    writer: W,
+    compression_level: u32,
+    limits: &DecompressionLimits,
+) -> io::Result<TranscodeReport> {
+    to.validate_level(compression_level)?;
+    let mut decoder = LimitedDecoder::with_codec(from, reader, *limits)?;
+    let output = compress_stream_checked(to, &mut decoder, writer, compression_level)?;
+
+    Ok(TranscodeReport {
+        source_bytes: decoder.compressed_bytes(),
+        output,
+    })
+}
+
+/// Like [`transcode_stream`], for files. The partially written output file
+/// is removed if transcoding fails.
+pub fn transcode_file(
+    from: &dyn Codec,
+    to: &dyn Codec,
+    input_file: &str,
+    output_file: &str,
+    compression_level: u32,
+    limits: &DecompressionLimits,
+) -> io::Result<TranscodeReport> {
+    let input = BufReader::new(File::open(input_file)?);
+    let output = BufWriter::new(File::create(output_file)?);
+
+    transcode_stream(from, to, input, output, compression_level, limits).inspect_err(|_| {
+        let _ = fs::remove_file(output_file);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::sha256_file;
+    use crate::codec::{codecs, Gzip};
+    use crate::{compress_file_with, compress_stream_with, decompress_file_with};
+    use std::path::Path;
+
+    #[test]
+    fn test_transcode_between_every_pair() -> io::Result<()> {
+        let data = b"transcode me, losslessly ".repeat(2000);
+
+        for from in codecs() {
+            let mut source = Vec::new();
+            compress_stream_with(*from, &data[..], &mut source, from.default_level())?;
+
+            for to in codecs() {
+                let mut target = Vec::new();
+                let report = transcode_stream(
+                    *from,
+                    *to,
+                    &source[..],
+                    &mut target,
+                    to.default_level(),
+                    &DecompressionLimits::default(),
+                )?;
+                assert_eq!(report.source_bytes, source.len() as u64);
+                assert_eq!(report.output.report.bytes_read, data.len() as u64);
+                assert_eq!(report.output.report.bytes_written, target.len() as u64);
+
+                let mut decoded = Vec::new();
+                crate::decompress_stream_with(
+                    *to,
+                    &target[..],
+                    &mut decoded,
+                    &DecompressionLimits::default(),
+                )?;
+                assert_eq!(decoded, data, "{} to {}", from.name(), to.name());
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_transcode_file_reports_original_digest() -> io::Result<()> {
+        let original_path = "test_transcode_original.txt";
+        let source_path = "test_transcode_source.gz";
+        let target_path = "test_transcode_target.gz";
+        let restored_path = "test_transcode_restored.txt";
+        fs::write(original_path, b"digest check ".repeat(500))?;
+        compress_file_with(&Gzip, original_path, source_path, 1)?;
+
+        let report = transcode_file(
+            &Gzip,
+            &Gzip,
+            source_path,
+            target_path,
+            9,
+            &DecompressionLimits::default(),
+        )?;
+        assert_eq!(report.output.original_sha256, sha256_file(original_path)?);
+        assert_eq!(report.output.compressed_sha256, sha256_file(target_path)?);
+
+        decompress_file_with(
+            &Gzip,
+            target_path,
+            restored_path,
+            &DecompressionLimits::default(),
+        )?;
+        assert_eq!(fs::read(restored_path)?, fs::read(original_path)?);
+
+        // Corrupt input fails and leaves no output behind
+        fs::write(source_path, b"not gzip at all")?;
+        assert!(transcode_file(
+            &Gzip,
+            &Gzip,
+            source_path,
+            target_path,
+            9,
+            &DecompressionLimits::default(),
+        )
+        .is_err());
+        assert!(!Path::new(target_path).exists());
+
+        // Cleanup
+        fs::remove_file(original_path)?;
+        fs::remove_file(source_path)?;
+        fs::remove_file(restored_path)?;
+
+        Ok(())
+    }
+}
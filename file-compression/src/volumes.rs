@@ -0,0 +1,527 @@
+use crate::checksum::{HashingReader, HashingWriter};
+use crate::codec::{codec_by_name, Codec};
+use crate::decompress::{decompress_stream_with, DecompressionLimits};
+use crate::{compress_stream_with, CompressionReport};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// First line of every manifest.
+const MANIFEST_HEADER: &str = "# file-compression volumes v1";
+
+/// One volume of a set, in the order it must be read back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Volume {
+    /// File name, relative to the manifest's directory.
+    pub file_name: String,
+    pub size: u64,
+    /// Hex SHA-256 of the volume.
+    pub sha256: String,
+}
+
+/// Describes a compressed stream split into volumes.
+///
+/// Stored as text: `# key: value` lines for the settings followed by one
+/// `<sha256>  <file name>` line per volume, in order, so `sha256sum -c`
+/// can check a volume set directly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolumeManifest {
+    /// Name of the codec, as accepted by [`codec_by_name`].
+    pub codec: String,
+    pub level: u32,
+    /// Maximum size of each volume.
+    pub volume_size: u64,
+    pub volumes: Vec<Volume>,
+}
+
+impl VolumeManifest {
+    /// Combined size of all volumes.
+    pub fn total_size(&self) -> u64 {
+        self.volumes.iter().map(|volume| volume.size).sum()
+    }
+
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", MANIFEST_HEADER)?;
+        writeln!(writer, "# codec: {}", self.codec)?;
+        writeln!(writer, "# level: {}", self.level)?;
+        writeln!(writer, "# volume-size: {}", self.volume_size)?;
+        for volume in &self.volumes {
+            writeln!(writer, "# size: {}", volume.size)?;
+            writeln!(writer, "{}  {}", volume.sha256, volume.file_name)?;
+        }
+        writer.flush()
+    }
+
+    /// Parses a manifest written by [`VolumeManifest::write_to`].
+    pub fn read_from<R: BufRead>(reader: R) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut lines = reader.lines();
+        if lines.next().transpose()?.as_deref() != Some(MANIFEST_HEADER) {
+            return Err(invalid("not a volume manifest".to_string()));
+        }
+
+        let mut codec = None;
+        let mut level = None;
+        let mut volume_size = None;
+        let mut size = None;
+        let mut volumes = Vec::new();
+        for line in lines {
+            let line = line?;
+            if let Some(setting) = line.strip_prefix("# ") {
+                let (key, value) = setting
+                    .split_once(": ")
+                    .ok_or_else(|| invalid(format!("malformed manifest line: {}", line)))?;
+                let number = || {
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| invalid(format!("invalid {} in manifest: {}", key, value)))
+                };
+                match key {
+                    "codec" => codec = Some(value.to_string()),
+                    "level" => {
+                        level = Some(u32::try_from(number()?).map_err(|_| {
+                            invalid(format!("invalid {} in manifest: {}", key, value))
+                        })?)
+                    }
+                    "volume-size" => volume_size = Some(number()?),
+                    "size" => size = Some(number()?),
+                    // Settings added by later versions
+                    _ => {}
+                }
+            } else if let Some((sha256, file_name)) = line.split_once("  ") {
+                // Names are kept to one directory so a manifest cannot point
+                // reads outside of it
+                if Path::new(file_name).file_name() != Some(file_name.as_ref()) {
+                    return Err(invalid(format!("invalid volume name: {}", file_name)));
+                }
+                volumes.push(Volume {
+                    file_name: file_name.to_string(),
+                    size: size
+                        .take()
+                        .ok_or_else(|| invalid(format!("missing size for {}", file_name)))?,
+                    sha256: sha256.to_string(),
+                });
+            } else if !line.is_empty() {
+                return Err(invalid(format!("malformed manifest line: {}", line)));
+            }
+        }
+
+        let missing = |key: &str| invalid(format!("manifest has no {}", key));
+        Ok(Self {
+            codec: codec.ok_or_else(|| missing("codec"))?,
+            level: level.ok_or_else(|| missing("level"))?,
+            volume_size: volume_size.ok_or_else(|| missing("volume-size"))?,
+            volumes,
+        })
+    }
+}
+
+/// Path of the manifest for volumes written with `output_prefix`.
+pub fn manifest_path(output_prefix: &str) -> String {
+    format!("{}.manifest", output_prefix)
+}
+
+/// Writer that splits its output into numbered volumes of at most
+/// `volume_size` bytes: `{prefix}.001`, `{prefix}.002` and so on.
+///
+/// Call [`VolumeWriter::finish`] to write the manifest, or
+/// [`VolumeWriter::discard`] to remove the volumes written so far.
+pub struct VolumeWriter {
+    prefix: String,
+    manifest: VolumeManifest,
+    current: Option<HashingWriter<BufWriter<File>>>,
+    current_size: u64,
+}
+
+impl VolumeWriter {
+    /// Starts a volume set for data compressed with `codec` at `level`.
+    pub fn new(
+        output_prefix: &str,
+        volume_size: u64,
+        codec: &dyn Codec,
+        level: u32,
+    ) -> io::Result<Self> {
+        if volume_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "volume size must be greater than zero",
+            ));
+        }
+
+        Ok(Self {
+            prefix: output_prefix.to_string(),
+            manifest: VolumeManifest {
+                codec: codec.name().to_string(),
+                level,
+                volume_size,
+                volumes: Vec::new(),
+            },
+            current: None,
+            current_size: 0,
+        })
+    }
+
+    fn directory(&self) -> PathBuf {
+        Path::new(&self.prefix)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default()
+    }
+
+    /// Closes the current volume, recording its size and digest.
+    fn close_volume(&mut self) -> io::Result<()> {
+        if let Some(mut current) = self.current.take() {
+            current.flush()?;
+            let volume = self.manifest.volumes.last_mut().expect("volume was opened");
+            volume.sha256 = current.finish();
+            volume.size = self.current_size;
+        }
+        Ok(())
+    }
+
+    fn open_volume(&mut self) -> io::Result<()> {
+        let path = format!("{}.{:03}", self.prefix, self.manifest.volumes.len() + 1);
+        let file_name = Path::new(&path)
+            .file_name()
+            .expect("volume path has a file name")
+            .to_string_lossy()
+            .into_owned();
+        self.current = Some(HashingWriter::new(BufWriter::new(File::create(&path)?)));
+        self.current_size = 0;
+        self.manifest.volumes.push(Volume {
+            file_name,
+            size: 0,
+            sha256: String::new(),
+        });
+        Ok(())
+    }
+
+    /// Closes the last volume and writes the manifest next to the volumes.
+    pub fn finish(mut self) -> io::Result<VolumeManifest> {
+        // Even empty output gets one volume, so the set is never empty
+        if self.manifest.volumes.is_empty() {
+            self.open_volume()?;
+        }
+        self.close_volume()?;
+
+        let file = BufWriter::new(File::create(manifest_path(&self.prefix))?);
+        self.manifest.write_to(file)?;
+        Ok(self.manifest)
+    }
+
+    /// Removes every volume written so far.
+    pub fn discard(mut self) -> io::Result<()> {
+        self.current = None;
+        let directory = self.directory();
+        for volume in &self.manifest.volumes {
+            fs::remove_file(directory.join(&volume.file_name))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_some() && self.current_size == self.manifest.volume_size {
+            self.close_volume()?;
+        }
+        if self.current.is_none() {
+            self.open_volume()?;
+        }
+
+        let room = self.manifest.volume_size - self.current_size;
+        let len = buf.len().min(usize::try_from(room).unwrap_or(usize::MAX));
+        let current = self.current.as_mut().expect("volume is open");
+        let written = current.write(&buf[..len])?;
+        self.current_size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(current) => current.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Compresses `input_file` with `codec` into volumes of at most
+/// `volume_size` bytes named after `output_prefix`, plus a manifest at
+/// [`manifest_path`]`(output_prefix)`. Nothing is left behind on failure.
+pub fn compress_file_to_volumes(
+    codec: &dyn Codec,
+    input_file: &str,
+    output_prefix: &str,
+    compression_level: u32,
+    volume_size: u64,
+) -> io::Result<VolumeManifest> {
+    let input = BufReader::new(File::open(input_file)?);
+    let mut output = VolumeWriter::new(output_prefix, volume_size, codec, compression_level)?;
+
+    match compress_stream_with(codec, input, &mut output, compression_level) {
+        Ok(_) => output.finish(),
+        Err(e) => {
+            let _ = output.discard();
+            Err(e)
+        }
+    }
+}
+
+/// Reads a volume set back as one stream, checking each volume against the
+/// manifest as it is finished. Fails with `InvalidData` on a mismatch.
+///
+/// A decoder may stop before the end of the last volume, so call
+/// [`VolumeReader::finish`] once done to check the volumes not read to the
+/// end.
+pub struct VolumeReader {
+    directory: PathBuf,
+    volumes: std::vec::IntoIter<Volume>,
+    current: Option<(Volume, u64, HashingReader<BufReader<File>>)>,
+}
+
+impl VolumeReader {
+    /// Opens the volumes listed in the manifest at `manifest_file`, which
+    /// are looked up in the manifest's directory.
+    pub fn open(manifest_file: &str) -> io::Result<(Self, VolumeManifest)> {
+        let manifest = VolumeManifest::read_from(BufReader::new(File::open(manifest_file)?))?;
+        let directory = Path::new(manifest_file)
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        let reader = Self {
+            directory,
+            volumes: manifest.volumes.clone().into_iter(),
+            current: None,
+        };
+        Ok((reader, manifest))
+    }
+
+    /// Reads the rest of the set, checking every volume not yet checked
+    /// against the manifest.
+    pub fn finish(mut self) -> io::Result<()> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok(())
+    }
+
+    fn check_current(&mut self) -> io::Result<()> {
+        if let Some((volume, size, reader)) = self.current.take() {
+            let sha256 = reader.finish();
+            if size != volume.size || sha256 != volume.sha256 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "volume {} is {} bytes with SHA-256 {}, manifest expects {} bytes with {}",
+                        volume.file_name, size, sha256, volume.size, volume.sha256
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if let Some((_, size, reader)) = &mut self.current {
+                let n = reader.read(buf)?;
+                if n > 0 {
+                    *size += n as u64;
+                    return Ok(n);
+                }
+                self.check_current()?;
+            }
+
+            match self.volumes.next() {
+                Some(volume) => {
+                    let file = File::open(self.directory.join(&volume.file_name))?;
+                    self.current = Some((volume, 0, HashingReader::new(BufReader::new(file))));
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Reassembles and decompresses the volume set described by
+/// `manifest_file` into `writer`, enforcing `limits`.
+pub fn decompress_volumes_stream<W: Write>(
+    manifest_file: &str,
+    writer: W,
+    limits: &DecompressionLimits,
+) -> io::Result<CompressionReport> {
+    let (mut reader, manifest) = VolumeReader::open(manifest_file)?;
+    let codec = codec_by_name(&manifest.codec).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("codec {} is not available in this build", manifest.codec),
+        )
+    })?;
+    let report = decompress_stream_with(codec, &mut reader, writer, limits)?;
+    reader.finish()?;
+    Ok(report)
+}
+
+/// Like [`decompress_volumes_stream`], writing to `output_file`. The
+/// partially written output file is removed if decompression fails.
+pub fn decompress_volumes(
+    manifest_file: &str,
+    output_file: &str,
+    limits: &DecompressionLimits,
+) -> io::Result<CompressionReport> {
+    let output = BufWriter::new(File::create(output_file)?);
+    decompress_volumes_stream(manifest_file, output, limits).inspect_err(|_| {
+        let _ = fs::remove_file(output_file);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::Gzip;
+
+    fn sample(len: usize) -> Vec<u8> {
+        let mut state = 0x1234_5678_9abc_def1u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 40) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_volumes_round_trip() -> io::Result<()> {
+        let dir = Path::new("test_volumes_round_trip");
+        fs::create_dir_all(dir)?;
+        let input_path = dir.join("input.bin");
+        let output_path = dir.join("restored.bin");
+        let prefix = dir.join("input.bin.gz");
+        let prefix = prefix.to_str().unwrap();
+        let data = sample(50_000);
+        fs::write(&input_path, &data)?;
+
+        let manifest =
+            compress_file_to_volumes(&Gzip, input_path.to_str().unwrap(), prefix, 6, 8 * 1024)?;
+        assert!(manifest.volumes.len() > 5);
+        assert!(manifest
+            .volumes
+            .iter()
+            .all(|volume| volume.size <= 8 * 1024));
+        assert_eq!(manifest.volumes[0].file_name, "input.bin.gz.001");
+        assert_eq!(
+            manifest.total_size(),
+            manifest
+                .volumes
+                .iter()
+                .map(|volume| fs::metadata(dir.join(&volume.file_name)).unwrap().len())
+                .sum::<u64>()
+        );
+        assert_eq!(
+            VolumeManifest::read_from(BufReader::new(File::open(manifest_path(prefix))?))?,
+            manifest
+        );
+
+        decompress_volumes(
+            &manifest_path(prefix),
+            output_path.to_str().unwrap(),
+            &DecompressionLimits::default(),
+        )?;
+        assert_eq!(fs::read(&output_path)?, data);
+
+        // Cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tampered_volume_is_rejected() -> io::Result<()> {
+        let dir = Path::new("test_volumes_tampered");
+        fs::create_dir_all(dir)?;
+        let input_path = dir.join("input.bin");
+        let prefix = dir.join("input.bin.gz");
+        let prefix = prefix.to_str().unwrap();
+        fs::write(&input_path, sample(20_000))?;
+
+        compress_file_to_volumes(&Gzip, input_path.to_str().unwrap(), prefix, 0, 4096)?;
+        let second = dir.join("input.bin.gz.002");
+        let mut bytes = fs::read(&second)?;
+        bytes[100] ^= 1;
+        fs::write(&second, bytes)?;
+
+        let err = decompress_volumes_stream(
+            &manifest_path(prefix),
+            io::sink(),
+            &DecompressionLimits::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finish_checks_unread_volumes() -> io::Result<()> {
+        let dir = Path::new("test_volumes_finish");
+        fs::create_dir_all(dir)?;
+        let input_path = dir.join("input.bin");
+        let prefix = dir.join("input.bin.gz");
+        let prefix = prefix.to_str().unwrap();
+        fs::write(&input_path, sample(20_000))?;
+
+        let manifest =
+            compress_file_to_volumes(&Gzip, input_path.to_str().unwrap(), prefix, 0, 4096)?;
+        let last = dir.join(&manifest.volumes.last().unwrap().file_name);
+        let mut bytes = fs::read(&last)?;
+        *bytes.last_mut().unwrap() ^= 1;
+        fs::write(&last, bytes)?;
+
+        // Only the first volume is read before finishing
+        let (mut reader, _) = VolumeReader::open(&manifest_path(prefix))?;
+        reader.read_exact(&mut [0; 16])?;
+        let err = reader.finish().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Cleanup
+        fs::remove_dir_all(dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_rejects_oversized_level() {
+        let manifest = format!(
+            "{}
+# codec: gzip
+# level: 4294967302
+# volume-size: 10
+",
+            MANIFEST_HEADER
+        );
+        let err = VolumeManifest::read_from(manifest.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_manifest_rejects_paths() {
+        let manifest = format!(
+            "{}\n# codec: gzip\n# level: 6\n# volume-size: 10\n# size: 3\nabc  ../escape.001\n",
+            MANIFEST_HEADER
+        );
+        let err = VolumeManifest::read_from(manifest.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
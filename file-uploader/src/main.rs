@@ -1,6 +1,7 @@
 use pbr::ProgressBar;
 use reqwest::multipart;
 use reqwest::Client;
+use serde::Deserialize;
 use std::{
     env,
     fs::File,
@@ -11,6 +12,16 @@ use std::{
 
 const FILE_FIELD: &str = "file";
 const COMPRESSION_LEVEL_FIELD: &str = "compression_level";
+const ALGORITHM_FIELD: &str = "algorithm";
+/// Server used unless `--server` or this variable names another one.
+const SERVER_ENV: &str = "UPLOAD_SERVER_URL";
+const DEFAULT_SERVER: &str = "http://localhost:3000";
+
+/// Body the server sends with every error status.
+#[derive(Deserialize)]
+struct ErrorResponse {
+    message: String,
+}
 
 async fn read_file(file_path: &str) -> io::Result<(String, Vec<u8>)> {
     let file_name = Path::new(file_path)
@@ -30,20 +41,33 @@ async fn upload_file(
     url: &str,
     file_name: String,
     file_data: Vec<u8>,
+    algorithm: Option<&str>,
     compression_level: Option<u32>,
-) -> Result<String, reqwest::Error> {
+) -> Result<String, Box<dyn std::error::Error>> {
     let part = multipart::Part::bytes(file_data)
         .file_name(file_name.clone())
         .mime_str("application/octet-stream")?;
 
     let mut form = multipart::Form::new().part(FILE_FIELD, part);
 
+    if let Some(algorithm) = algorithm {
+        form = form.text(ALGORITHM_FIELD, algorithm.to_string());
+    }
     if let Some(level) = compression_level {
         form = form.text(COMPRESSION_LEVEL_FIELD, level.to_string());
     }
 
     let response = client.post(url).multipart(form).send().await?;
-    response.text().await
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        // Fall back to the raw body if a proxy answered instead of the server
+        let message = serde_json::from_str::<ErrorResponse>(&body)
+            .map(|error| error.message)
+            .unwrap_or(body);
+        return Err(format!("{}: {}", status, message).into());
+    }
+    Ok(body)
 }
 
 #[tokio::main]
@@ -51,30 +75,35 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
         eprintln!(
-            "Usage: {} [--compression-level <1-9>] <file_path1> <file_path2> ...",
+            "Usage: {} [--server <url>] [--algorithm <name>] [--compression-level <level>] <file_path1> <file_path2> ...",
             args[0]
         );
         return Ok(());
     }
 
-    let (compression_level, files) = if args[1] == "--compression-level" && args.len() > 3 {
-        match args[2].parse::<u32>() {
-            Ok(level @ 1..=9) => (Some(level), &args[3..]),
-            Ok(_) => {
-                eprintln!("Compression level must be between 1 and 9");
-                return Ok(());
-            }
-            Err(_) => {
-                eprintln!("Invalid compression level");
-                return Ok(());
-            }
+    // Leading flags; the server validates the level against the algorithm
+    let mut server = env::var(SERVER_ENV).unwrap_or_else(|_| DEFAULT_SERVER.to_string());
+    let mut algorithm = None;
+    let mut compression_level = None;
+    let mut files = &args[1..];
+    while let [flag, value, rest @ ..] = files {
+        match flag.as_str() {
+            "--server" => server = value.clone(),
+            "--algorithm" => algorithm = Some(value.as_str()),
+            "--compression-level" => match value.parse::<u32>() {
+                Ok(level) => compression_level = Some(level),
+                Err(_) => {
+                    eprintln!("Invalid compression level");
+                    return Ok(());
+                }
+            },
+            _ => break,
         }
-    } else {
-        (None, &args[1..])
-    };
+        files = rest;
+    }
 
     let client = Client::new();
-    let url = "http://localhost:3000/uploader/upload";
+    let url = format!("{}/uploader/upload", server.trim_end_matches('/'));
 
     println!("Uploading {} files...", files.len());
     let mut pb = ProgressBar::new(files.len() as u64);
@@ -89,7 +118,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         match read_file(file_path).await {
             Ok((file_name, file_data)) => {
-                match upload_file(&client, url, file_name, file_data, compression_level).await {
+                match upload_file(
+                    &client,
+                    &url,
+                    file_name,
+                    file_data,
+                    algorithm,
+                    compression_level,
+                )
+                .await
+                {
                     Ok(response) => {
                         println!("Server response: {}", response);
                         success_count += 1;
@@ -113,6 +151,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     if failed_files.is_empty() {
         println!("✅ All files uploaded successfully!");
+        if let Some(algorithm) = algorithm {
+            println!("📦 Compression algorithm: {}", algorithm);
+        }
         if let Some(level) = compression_level {
             println!("📦 Compression level: {}", level);
         }
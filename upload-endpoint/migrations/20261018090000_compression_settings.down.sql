@@ -0,0 +1,3 @@
+ALTER TABLE compression_tasks
+    DROP COLUMN IF EXISTS compression_level,
+    DROP COLUMN IF EXISTS algorithm;
@@ -0,0 +1,4 @@
+-- Record the compression settings requested for each upload
+ALTER TABLE compression_tasks
+    ADD COLUMN algorithm TEXT NOT NULL DEFAULT 'gzip',
+    ADD COLUMN compression_level INTEGER NOT NULL DEFAULT 6;
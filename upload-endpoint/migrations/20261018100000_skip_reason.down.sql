@@ -0,0 +1 @@
+ALTER TABLE compression_tasks DROP COLUMN IF EXISTS skip_reason;
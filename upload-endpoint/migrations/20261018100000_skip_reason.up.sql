@@ -0,0 +1,2 @@
+-- Why an automatically handled upload was stored without compression
+ALTER TABLE compression_tasks ADD COLUMN skip_reason TEXT;
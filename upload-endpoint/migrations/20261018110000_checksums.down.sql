@@ -0,0 +1,3 @@
+ALTER TABLE compression_tasks
+    DROP COLUMN IF EXISTS compressed_sha256,
+    DROP COLUMN IF EXISTS original_sha256;
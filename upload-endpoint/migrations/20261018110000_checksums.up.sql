@@ -0,0 +1,4 @@
+-- SHA-256 digests of the uploaded file and of the compressed output
+ALTER TABLE compression_tasks
+    ADD COLUMN original_sha256 TEXT,
+    ADD COLUMN compressed_sha256 TEXT;
@@ -0,0 +1,3 @@
+-- Version of the zstd dictionary a task was compressed with, if any
+ALTER TABLE compression_tasks
+    ADD COLUMN dictionary_version INTEGER;
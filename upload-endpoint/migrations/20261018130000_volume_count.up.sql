@@ -0,0 +1,3 @@
+-- Number of volumes a task's output was split into, if it was split
+ALTER TABLE compression_tasks
+    ADD COLUMN volume_count INTEGER;
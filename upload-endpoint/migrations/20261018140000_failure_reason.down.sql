@@ -0,0 +1,2 @@
+ALTER TABLE compression_tasks
+    DROP COLUMN IF EXISTS failure_reason;
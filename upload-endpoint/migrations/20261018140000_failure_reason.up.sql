@@ -0,0 +1,3 @@
+-- Why a task failed, e.g. which file could not be read or written
+ALTER TABLE compression_tasks
+    ADD COLUMN failure_reason TEXT;
@@ -0,0 +1,9 @@
+-- Uploads are stored under generated keys, and the name the client sent is
+-- kept here for downloads
+ALTER TABLE compression_tasks
+    ADD COLUMN original_name TEXT;
+
+-- Earlier uploads were stored as `{unix_seconds}_{name}`
+UPDATE compression_tasks
+    SET original_name = substring(file_name FROM position('_' IN file_name) + 1)
+    WHERE position('_' IN file_name) > 0;
@@ -0,0 +1,17 @@
+-- Resumable (tus) uploads in progress. The data is written to
+-- `<storage.tus_dir>/<id>`, outside the served directories, and
+-- `upload_offset` counts the bytes of it that were stored, so an upload can
+-- resume after a restart
+CREATE TABLE tus_uploads (
+    id TEXT PRIMARY KEY,
+    upload_length BIGINT NOT NULL,
+    upload_offset BIGINT NOT NULL DEFAULT 0,
+    -- `Upload-Metadata` as the client sent it
+    metadata TEXT,
+    original_name TEXT NOT NULL,
+    algorithm TEXT NOT NULL,
+    compression_level INTEGER NOT NULL,
+    -- Set once the upload is complete and queued for compression
+    task_id INTEGER REFERENCES compression_tasks (id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -1,21 +1,17 @@
 // upload-endpoint/src/db.rs
-use dotenv::dotenv;
 use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::env;
 
-pub async fn establish_connection() -> Result<PgPool, String> {
-    dotenv().ok();
+use crate::config::DatabaseConfig;
 
-    let database_url = env::var("DATABASE_URL")
-        .map_err(|_| "DATABASE_URL must be set in .env file".to_string())?;
-
-    println!("🛠️  RAW DATABASE_URL: {}", database_url);
+pub async fn establish_connection(config: &DatabaseConfig) -> Result<PgPool, String> {
+    // Checked when the configuration is loaded
+    let database_url = config.url.as_deref().unwrap_or_default();
 
     let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
+        .max_connections(config.max_connections)
+        .connect(database_url)
         .await
-        .map_err(|e| format!("Connection failed with URL '{}': {}", database_url, e))?;
+        .map_err(|e| format!("Connection failed: {}", e))?;
 
     println!("✅ Connection pool created successfully");
 
@@ -0,0 +1,77 @@
+use file_compression::{codec::Codec, CompressionOptionsBuilder, ExternalCodec};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::config::ExternalCodecConfig;
+
+/// Codecs that run external programs, configured under `[external_codecs]`
+/// and offered next to the built-in ones.
+#[derive(Debug, Default)]
+pub struct ExternalCodecs {
+    codecs: Vec<ExternalCodec>,
+}
+
+impl ExternalCodecs {
+    /// Builds the configured codecs, which [`crate::config::Config::load`]
+    /// has already validated.
+    pub fn from_config(configs: &BTreeMap<String, ExternalCodecConfig>) -> Result<Self, String> {
+        let codecs = configs
+            .iter()
+            .map(|(name, config)| load_codec(name, config))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { codecs })
+    }
+
+    /// Names of the loaded codecs.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.codecs.iter().map(|codec| codec.name()).collect()
+    }
+
+    /// Looks a codec up by name among the built-in and external codecs.
+    pub fn codec_by_name(&self, name: &str) -> Option<&dyn Codec> {
+        file_compression::codec_by_name(name).or_else(|| {
+            self.codecs
+                .iter()
+                .find(|codec| codec.name().eq_ignore_ascii_case(name))
+                .map(|codec| codec as &dyn Codec)
+        })
+    }
+
+    /// Like [`CompressionOptionsBuilder::codec_name`], also finding external
+    /// codecs.
+    pub fn with_codec_name<'a>(
+        &'a self,
+        builder: CompressionOptionsBuilder<'a>,
+        name: &str,
+    ) -> CompressionOptionsBuilder<'a> {
+        match self.codec_by_name(name) {
+            Some(codec) => builder.codec(codec),
+            None => builder.codec_name(name),
+        }
+    }
+}
+
+fn load_codec(name: &str, config: &ExternalCodecConfig) -> Result<ExternalCodec, String> {
+    let extension = config.extension.as_deref().unwrap_or(name);
+
+    // Codec names are static everywhere else, and these live as long as
+    // the server does
+    let mut codec = ExternalCodec::new(
+        Box::leak(name.to_string().into_boxed_str()),
+        Box::leak(
+            extension
+                .trim_start_matches('.')
+                .to_string()
+                .into_boxed_str(),
+        ),
+        config.compress_command(name)?,
+        config.decompress_command(name)?,
+    );
+    if let Some((levels, default_level)) = config.level_range(name)? {
+        codec = codec.with_levels(levels, default_level);
+    }
+    if let Some(seconds) = config.timeout_secs {
+        codec = codec.with_timeout(Duration::from_secs(seconds));
+    }
+    Ok(codec)
+}
@@ -1,23 +1,43 @@
 use axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{rejection::PathRejection, Path, State},
+    Json,
 };
-use sqlx::PgPool;
+use serde::Serialize;
 use sqlx::Row;
 
+use crate::handlers::{ApiError, ErrorResponse};
+use crate::state::AppState;
+
 use utoipa::ToSchema;
 
-#[derive(ToSchema)]
+#[derive(Serialize, ToSchema)]
 pub struct StatusResponse {
     pub task_id: i32,
+    /// Key the upload is stored under.
     pub file_name: String,
+    /// Name the file was uploaded as.
+    pub original_name: Option<String>,
     pub status: String,
-}
-
-#[derive(ToSchema)]
-pub struct ErrorResponse {
-    pub message: String,
+    pub algorithm: String,
+    pub compression_level: i32,
+    /// Set when the file was stored without compression, e.g.
+    /// `already compressed (jpeg)`.
+    pub skip_reason: Option<String>,
+    /// Hex SHA-256 of the uploaded file, once compressed.
+    pub original_sha256: Option<String>,
+    /// Hex SHA-256 of the compressed output, once compressed.
+    pub compressed_sha256: Option<String>,
+    /// Version of the zstd dictionary needed to decompress the output.
+    pub dictionary_version: Option<i32>,
+    /// Number of volumes the output was split into, if it was split.
+    pub volume_count: Option<i32>,
+    /// Why the task failed, e.g. `failed to read uploads/...: ...`, or why
+    /// a completed task kept its output after a failed transcode.
+    pub failure_reason: Option<String>,
+    /// Uncompressed bytes processed so far, while the task is running.
+    pub bytes_processed: Option<u64>,
+    /// Size of the uploaded file, while the task is running.
+    pub total_bytes: Option<u64>,
 }
 
 #[utoipa::path(
@@ -35,29 +55,41 @@ pub struct ErrorResponse {
 )]
 
 pub async fn check_status(
-    Path(task_id): Path<i32>,
-    Extension(pool): Extension<PgPool>,
-) -> impl IntoResponse {
-    match sqlx::query("SELECT file_name, status FROM compression_tasks WHERE id = $1")
-        .bind(task_id)
-        .fetch_one(&pool)
-        .await
-    {
-        Ok(record) => {
-            let file_name: String = record.get("file_name");
-            let status: String = record.get("status");
-            return (
-                StatusCode::OK,
-                format!("Task {}: {} (status: {})", task_id, file_name, status),
-            );
+    task_id: Result<Path<i32>, PathRejection>,
+    State(AppState { pool, jobs, .. }): State<AppState>,
+) -> Result<Json<StatusResponse>, ApiError> {
+    let Path(task_id) = task_id?;
+    let record = sqlx::query(
+        "SELECT file_name, original_name, status, algorithm, compression_level, skip_reason, original_sha256, compressed_sha256,
+                dictionary_version, volume_count, failure_reason
+         FROM compression_tasks WHERE id = $1",
+    )
+    .bind(task_id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => {
+            ApiError::not_found(format!("Task with ID {} not found", task_id))
         }
-        Err(sqlx::Error::RowNotFound) => (
-            StatusCode::NOT_FOUND,
-            format!("Task with ID {} not found", task_id),
-        ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Database error: {}", e),
-        ),
-    }
+        e => ApiError::database(e),
+    })?;
+
+    let progress = jobs.get(task_id).map(|job| *job.progress.lock().unwrap());
+
+    Ok(Json(StatusResponse {
+        task_id,
+        file_name: record.get("file_name"),
+        original_name: record.get("original_name"),
+        status: record.get("status"),
+        algorithm: record.get("algorithm"),
+        compression_level: record.get("compression_level"),
+        skip_reason: record.get("skip_reason"),
+        original_sha256: record.get("original_sha256"),
+        compressed_sha256: record.get("compressed_sha256"),
+        dictionary_version: record.get("dictionary_version"),
+        volume_count: record.get("volume_count"),
+        failure_reason: record.get("failure_reason"),
+        bytes_processed: progress.map(|p| p.bytes_processed),
+        total_bytes: progress.and_then(|p| p.total_bytes),
+    }))
 }
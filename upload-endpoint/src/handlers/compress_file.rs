@@ -1,113 +1,549 @@
-use axum::http::StatusCode;
-use axum::{extract::Extension, response::IntoResponse};
-use sqlx::{FromRow, PgPool};
-use std::{fs, io::Write, path::Path};
+use axum::{
+    extract::{
+        rejection::{PathRejection, QueryRejection},
+        Path, Query, State,
+    },
+    Json,
+};
+use file_compression::{
+    auto::AUTO,
+    checksum::{HashingReader, HashingWriter},
+    codec::{Codec, Gzip},
+    compress_file_auto_with_progress, compress_stream_with_options, decompress_stream_with,
+    dictionary::ZSTD_DICT,
+    manifest_path, sha256_file, verify_compressed_file, AutoOptions, CancellationToken,
+    ChecksumReport, CompressionError, CompressionOptions, Decision, DecompressionLimits,
+    DictionaryStore, GzipHeader, Progress, ProgressReader, VolumeReader, VolumeWriter,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+    sync::{Arc, Mutex},
+};
 use tokio::task;
 
+use crate::config::StorageConfig;
+use crate::external_codecs::ExternalCodecs;
+use crate::handlers::{ApiError, ErrorResponse};
+use crate::state::AppState;
+
 // Add this struct to represent the query results
 #[derive(FromRow)]
 struct CompressionTask {
     id: i32,
+    /// Key the upload is stored under.
     file_name: String,
+    /// Name the client uploaded the file as.
+    original_name: Option<String>,
+    algorithm: String,
+    compression_level: i32,
 }
 
 use utoipa::ToSchema;
 
-#[derive(ToSchema)]
+/// Handle on a compression job that is currently running.
+#[derive(Clone, Default)]
+pub struct RunningJob {
+    pub cancel: CancellationToken,
+    pub progress: Arc<Mutex<Progress>>,
+}
+
+/// Compression jobs currently running, by task ID.
+#[derive(Clone, Default)]
+pub struct RunningJobs(Arc<Mutex<HashMap<i32, RunningJob>>>);
+
+impl RunningJobs {
+    fn start(&self, task_id: i32) -> RunningJob {
+        let job = RunningJob::default();
+        self.0.lock().unwrap().insert(task_id, job.clone());
+        job
+    }
+
+    pub fn get(&self, task_id: i32) -> Option<RunningJob> {
+        self.0.lock().unwrap().get(&task_id).cloned()
+    }
+
+    fn finish(&self, task_id: i32) {
+        self.0.lock().unwrap().remove(&task_id);
+    }
+}
+
+/// Settings a task was actually compressed with, and the digests of its
+/// input and output.
+struct CompressionOutcome {
+    algorithm: String,
+    compression_level: i32,
+    skip_reason: Option<String>,
+    original_sha256: String,
+    compressed_sha256: String,
+    /// Dictionary the output needs for decompression.
+    dictionary_version: Option<i32>,
+    /// Number of volumes the output was split into.
+    volume_count: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct CompressParams {
+    /// Decompress each output and compare it with the original before
+    /// marking the task completed.
+    verify: Option<bool>,
+    /// Split each output into volumes of at most this many bytes.
+    volume_size: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct CompressionResponse {
     pub message: String,
     pub file_count: usize,
+    /// Tasks that started compressing.
+    pub task_ids: Vec<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CancelResponse {
+    pub task_id: i32,
+    pub message: String,
 }
 
 #[utoipa::path(
     post,
     path = "/compressor/compress",
+    params(
+        ("verify" = Option<bool>, Query, description = "Check that each output decompresses to the original before completing the task"),
+        ("volume_size" = Option<u64>, Query, description = "Split each output into numbered volumes of at most this many bytes, with a manifest. Not applied to auto tasks")
+    ),
     responses(
         (status = 200, description = "Compression started", body = CompressionResponse),
-        (status = 500, description = "Internal server error")
+        (status = 400, description = "Invalid volume size", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
     ),
     tag = "file-service"
 )]
 
-pub async fn compress_all_files(Extension(pool): Extension<PgPool>) -> impl IntoResponse {
-    // Ensure compressed directory exists
-    if let Err(e) = fs::create_dir_all("compressed") {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to create compressed directory: {}", e),
-        );
+pub async fn compress_all_files(
+    params: Result<Query<CompressParams>, QueryRejection>,
+    State(AppState {
+        config,
+        pool,
+        codecs,
+        jobs,
+        ..
+    }): State<AppState>,
+) -> Result<Json<CompressionResponse>, ApiError> {
+    let Query(params) = params?;
+    let verify = params.verify.unwrap_or(false);
+    let volume_size = params.volume_size;
+    if volume_size == Some(0) {
+        return Err(ApiError::bad_request(
+            "Volume size must be greater than zero",
+        ));
     }
 
-    // Get all pending files from database
-    let pending_files = match sqlx::query_as::<_, CompressionTask>(
-        "SELECT id, file_name FROM compression_tasks WHERE status = 'pending'",
+    // Ensure compressed directory exists
+    fs::create_dir_all(&config.storage.compressed_dir)
+        .map_err(|e| ApiError::internal(format!("Failed to create compressed directory: {}", e)))?;
+
+    // Claim all pending files in one statement, so overlapping requests
+    // never compress the same file twice
+    let pending_files = sqlx::query_as::<_, CompressionTask>(
+        "UPDATE compression_tasks SET status = 'processing'
+         WHERE status = 'pending'
+         RETURNING id, file_name, original_name, algorithm, compression_level",
     )
     .fetch_all(&pool)
     .await
-    {
-        Ok(files) => files,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Database error: {}", e),
-            );
-        }
-    };
+    .map_err(ApiError::database)?;
 
     let file_count = pending_files.len();
+    let task_ids: Vec<i32> = pending_files.iter().map(|task| task.id).collect();
 
     if file_count == 0 {
-        return (StatusCode::OK, "No files to compress".to_string());
+        return Ok(Json(CompressionResponse {
+            message: "No files to compress".to_string(),
+            file_count,
+            task_ids,
+        }));
     }
 
     // Process each file in background
     for task in pending_files {
         let pool = pool.clone();
-        let input_path = format!("uploads/{}", task.file_name);
-        let output_path = format!("compressed/{}.gz", task.file_name);
+        let jobs = jobs.clone();
+        let config = Arc::clone(&config);
+        let codecs = Arc::clone(&codecs);
 
         task::spawn(async move {
-            // Update status to processing
-            let _ = sqlx::query("UPDATE compression_tasks SET status = 'processing' WHERE id = $1")
-                .bind(task.id)
-                .execute(&pool)
-                .await;
-
-            // Perform compression
-            let result = compress_file(&input_path, &output_path);
-
-            // Update status based on result
-            let status = if result.is_ok() {
-                "completed"
-            } else {
-                "failed"
-            };
-
-            let _ = sqlx::query("UPDATE compression_tasks SET status = $1 WHERE id = $2")
-                .bind(status)
-                .bind(task.id)
-                .execute(&pool)
-                .await;
+            let task_id = task.id;
+            let job = jobs.start(task_id);
+
+            // Compress with the settings recorded at upload time, on the
+            // blocking pool so runtime threads stay free
+            let result = task::spawn_blocking(move || {
+                compress_task(&config.storage, &codecs, &task, verify, volume_size, &job)
+            })
+            .await
+            .unwrap_or_else(|e| Err(CompressionError::Io(io::Error::other(e))));
+            jobs.finish(task_id);
+
+            match result {
+                Ok(outcome) => {
+                    let _ = sqlx::query(
+                        "UPDATE compression_tasks
+                         SET status = 'completed', algorithm = $1, compression_level = $2, skip_reason = $3,
+                             original_sha256 = $4, compressed_sha256 = $5, dictionary_version = $6,
+                             volume_count = $7
+                         WHERE id = $8",
+                    )
+                    .bind(outcome.algorithm)
+                    .bind(outcome.compression_level)
+                    .bind(outcome.skip_reason)
+                    .bind(outcome.original_sha256)
+                    .bind(outcome.compressed_sha256)
+                    .bind(outcome.dictionary_version)
+                    .bind(outcome.volume_count)
+                    .bind(task_id)
+                    .execute(&pool)
+                    .await;
+                }
+                Err(e) => {
+                    let (status, failure_reason) = match e {
+                        CompressionError::Cancelled => ("cancelled", None),
+                        e => ("failed", Some(e.to_string())),
+                    };
+                    let _ = sqlx::query(
+                        "UPDATE compression_tasks SET status = $1, failure_reason = $2 WHERE id = $3",
+                    )
+                    .bind(status)
+                    .bind(failure_reason)
+                    .bind(task_id)
+                    .execute(&pool)
+                    .await;
+                }
+            }
         });
     }
 
-    (
-        StatusCode::OK,
-        format!("Started compressing {} files in background", file_count),
-    )
+    Ok(Json(CompressionResponse {
+        message: format!("Started compressing {} files in background", file_count),
+        file_count,
+        task_ids,
+    }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/compressor/cancel/{task_id}",
+    params(
+        ("task_id" = i32, Path, description = "Task ID to cancel")
+    ),
+    responses(
+        (status = 200, description = "Cancellation requested", body = CancelResponse),
+        (status = 404, description = "Task is not being compressed", body = ErrorResponse)
+    ),
+    tag = "file-service"
+)]
+
+pub async fn cancel_task(
+    task_id: Result<Path<i32>, PathRejection>,
+    State(AppState { jobs, .. }): State<AppState>,
+) -> Result<Json<CancelResponse>, ApiError> {
+    let Path(task_id) = task_id?;
+    let job = jobs
+        .get(task_id)
+        .ok_or_else(|| ApiError::not_found(format!("Task {} is not being compressed", task_id)))?;
+    job.cancel.cancel();
+
+    Ok(Json(CancelResponse {
+        task_id,
+        message: format!("Cancellation requested for task {}", task_id),
+    }))
 }
 
-fn compress_file(input_path: &str, output_path: &str) -> std::io::Result<()> {
-    // Read input file
-    let mut input = std::fs::File::open(input_path)?;
-    let mut buffer = Vec::new();
-    std::io::Read::read_to_end(&mut input, &mut buffer)?;
+/// Treats a failure while checking an output as a failure of its codec,
+/// unless it already says what went wrong.
+fn codec_error(codec: &dyn Codec, error: io::Error) -> CompressionError {
+    match CompressionError::from(error) {
+        CompressionError::Io(source) => CompressionError::Codec {
+            codec: codec.name().to_string(),
+            source,
+        },
+        error => error,
+    }
+}
+
+/// Gzip header carrying the original upload name, upload time and task ID,
+/// so `gunzip -N` restores the original file.
+fn gzip_header_for(
+    task: &CompressionTask,
+    input_path: &str,
+) -> Result<GzipHeader, CompressionError> {
+    Ok(GzipHeader {
+        file_name: task.original_name.clone(),
+        comment: Some(format!("task {}", task.id)),
+        ..GzipHeader::for_file(input_path).map_err(|e| CompressionError::read(input_path, e))?
+    })
+}
+
+/// Removes an output that failed verification, so it cannot be downloaded,
+/// and passes the error on.
+fn discard_unverified(output_path: &str, error: CompressionError) -> CompressionError {
+    let _ = fs::remove_file(output_path);
+    error
+}
+
+/// Compresses `input` into `output` with `options`, hashing both sides.
+fn compress_checked<R: io::Read, W: io::Write>(
+    input: R,
+    output: W,
+    options: &CompressionOptions,
+) -> Result<ChecksumReport, CompressionError> {
+    let mut input = HashingReader::new(input);
+    let mut output = HashingWriter::new(output);
+    let report = compress_stream_with_options(&mut input, &mut output, options)?;
+
+    Ok(ChecksumReport {
+        report,
+        original_sha256: input.finish(),
+        compressed_sha256: output.finish(),
+    })
+}
+
+fn compress_task(
+    storage: &StorageConfig,
+    codecs: &ExternalCodecs,
+    task: &CompressionTask,
+    verify: bool,
+    volume_size: Option<u64>,
+    job: &RunningJob,
+) -> Result<CompressionOutcome, CompressionError> {
+    let input_path = storage.upload_path(&task.file_name);
+    let output_prefix = storage.compressed_path(&task.file_name);
+
+    if task.algorithm == AUTO {
+        let outcome = compress_file_auto_with_progress(
+            &input_path,
+            &output_prefix,
+            &AutoOptions {
+                gzip_header: Some(gzip_header_for(task, &input_path)?),
+                ..AutoOptions::default()
+            },
+            &job.cancel,
+            |progress| *job.progress.lock().unwrap() = progress,
+        )?;
+        let output_path = outcome.output_file.to_string_lossy().into_owned();
+        let original_sha256 =
+            sha256_file(&input_path).map_err(|e| CompressionError::read(&input_path, e))?;
+        let compressed_sha256 =
+            sha256_file(&output_path).map_err(|e| CompressionError::read(&output_path, e))?;
+
+        return Ok(match outcome.decision {
+            Decision::Compress { codec, level, .. } => {
+                if verify {
+                    verify_compressed_file(
+                        codec,
+                        &output_path,
+                        &original_sha256,
+                        Some(outcome.report.bytes_read),
+                    )
+                    .map_err(|e| discard_unverified(&output_path, codec_error(codec, e)))?;
+                }
+                CompressionOutcome {
+                    algorithm: codec.name().to_string(),
+                    compression_level: level as i32,
+                    skip_reason: None,
+                    original_sha256,
+                    compressed_sha256,
+                    dictionary_version: None,
+                    volume_count: None,
+                }
+            }
+            Decision::Store(reason) => {
+                if verify && original_sha256 != compressed_sha256 {
+                    return Err(discard_unverified(
+                        &output_path,
+                        CompressionError::write(
+                            &output_path,
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "stored copy does not match the uploaded file",
+                            ),
+                        ),
+                    ));
+                }
+                CompressionOutcome {
+                    algorithm: "none".to_string(),
+                    compression_level: 0,
+                    skip_reason: Some(reason.to_string()),
+                    original_sha256,
+                    compressed_sha256,
+                    dictionary_version: None,
+                    volume_count: None,
+                }
+            }
+        });
+    }
+
+    // Dictionary tasks use the latest trained dictionary, or plain zstd
+    // until one has been trained
+    let dictionary = if task.algorithm == ZSTD_DICT {
+        DictionaryStore::new(&storage.dictionaries_dir)
+            .latest()
+            .map_err(|e| CompressionError::read(&storage.dictionaries_dir, e))?
+    } else {
+        None
+    };
+    let builder = CompressionOptions::builder().level(task.compression_level as u32);
+    let options = match &dictionary {
+        Some(dictionary) => builder.codec(dictionary),
+        None if task.algorithm == ZSTD_DICT => builder.codec_name("zstd"),
+        None => codecs.with_codec_name(builder, &task.algorithm),
+    }
+    .build()?;
+
+    // Gzip output carries the original upload name in its header, as with
+    // automatic selection
+    let gzip_header;
+    let options = if options.codec().name() == Gzip.name() {
+        gzip_header = gzip_header_for(task, &input_path)?;
+        CompressionOptions::builder()
+            .codec(&gzip_header)
+            .level(options.level())
+            .build()?
+    } else {
+        options
+    };
+    let codec = options.codec();
+
+    let output_path = format!("{}.{}", output_prefix, codec.extension());
+    let input = File::open(&input_path).map_err(|e| CompressionError::read(&input_path, e))?;
+    let total_bytes = input
+        .metadata()
+        .map_err(|e| CompressionError::read(&input_path, e))?
+        .len();
+    let input = BufReader::new(ProgressReader::new(
+        input,
+        Some(total_bytes),
+        job.cancel.clone(),
+        |progress| *job.progress.lock().unwrap() = progress,
+    ));
 
-    // Create output file and compress
-    let output = std::fs::File::create(output_path)?;
-    let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
-    encoder.write_all(&buffer)?;
-    encoder.finish()?;
+    if let Some(volume_size) = volume_size {
+        return compress_to_volumes(input, &output_path, &options, volume_size, verify)
+            .map_err(|e| e.with_paths(&input_path, &output_path))
+            .map(|(checked, volume_count)| CompressionOutcome {
+                algorithm: codec.name().to_string(),
+                compression_level: task.compression_level,
+                skip_reason: None,
+                original_sha256: checked.original_sha256,
+                compressed_sha256: checked.compressed_sha256,
+                dictionary_version: dictionary.as_ref().map(|d| d.version() as i32),
+                volume_count: Some(volume_count),
+            });
+    }
+
+    let output =
+        File::create(&output_path).map_err(|e| CompressionError::write(&output_path, e))?;
+    let checked = match compress_checked(input, BufWriter::new(output), &options) {
+        Ok(checked) => checked,
+        Err(e) => {
+            // Cancelled or failed jobs leave no partial output behind
+            let _ = fs::remove_file(&output_path);
+            return Err(e.with_paths(&input_path, &output_path));
+        }
+    };
+    if verify {
+        verify_compressed_file(
+            codec,
+            &output_path,
+            &checked.original_sha256,
+            Some(checked.report.bytes_read),
+        )
+        .map_err(|e| discard_unverified(&output_path, codec_error(codec, e)))?;
+    }
+
+    Ok(CompressionOutcome {
+        algorithm: codec.name().to_string(),
+        compression_level: task.compression_level,
+        skip_reason: None,
+        original_sha256: checked.original_sha256,
+        compressed_sha256: checked.compressed_sha256,
+        dictionary_version: dictionary.as_ref().map(|d| d.version() as i32),
+        volume_count: None,
+    })
+}
+
+/// Compresses `input` into volumes named after `output_path`, with a
+/// manifest next to them. Returns the digests and the number of volumes.
+fn compress_to_volumes<R: io::Read>(
+    input: R,
+    output_path: &str,
+    options: &CompressionOptions,
+    volume_size: u64,
+    verify: bool,
+) -> Result<(ChecksumReport, i32), CompressionError> {
+    let codec = options.codec();
+    let mut volumes = VolumeWriter::new(output_path, volume_size, codec, options.level())
+        .map_err(|e| CompressionError::write(output_path, e))?;
+    let checked = match compress_checked(input, &mut volumes, options) {
+        Ok(checked) => checked,
+        Err(e) => {
+            let _ = volumes.discard();
+            return Err(e);
+        }
+    };
+    let manifest = volumes
+        .finish()
+        .map_err(|e| CompressionError::write(manifest_path(output_path), e))?;
+
+    if verify {
+        if let Err(e) = verify_volumes(output_path, codec, &checked) {
+            // Unverified volumes must not stay downloadable
+            let directory = std::path::Path::new(output_path)
+                .parent()
+                .unwrap_or(std::path::Path::new(""));
+            for volume in &manifest.volumes {
+                let _ = fs::remove_file(directory.join(&volume.file_name));
+            }
+            let _ = fs::remove_file(manifest_path(output_path));
+            return Err(e);
+        }
+    }
 
+    Ok((checked, manifest.volumes.len() as i32))
+}
+
+/// Reassembles and decompresses the volumes named after `output_path` and
+/// compares the result with the uploaded file.
+fn verify_volumes(
+    output_path: &str,
+    codec: &dyn Codec,
+    checked: &ChecksumReport,
+) -> Result<(), CompressionError> {
+    // Reassembling checks every volume against the manifest on the way
+    let (mut reassembled, _) = VolumeReader::open(&manifest_path(output_path))
+        .map_err(|e| CompressionError::read(manifest_path(output_path), e))?;
+    let mut restored = HashingWriter::new(io::sink());
+    let report = decompress_stream_with(
+        codec,
+        &mut reassembled,
+        &mut restored,
+        &DecompressionLimits::unlimited(),
+    )
+    .and_then(|report| reassembled.finish().map(|_| report))
+    .map_err(|e| codec_error(codec, e))?;
+    if report.bytes_written != checked.report.bytes_read
+        || restored.finish() != checked.original_sha256
+    {
+        return Err(codec_error(
+            codec,
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("volumes of {} do not match the uploaded file", output_path),
+            ),
+        ));
+    }
     Ok(())
 }
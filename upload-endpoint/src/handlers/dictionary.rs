@@ -0,0 +1,108 @@
+use axum::{
+    extract::{rejection::QueryRejection, Query, State},
+    Json,
+};
+use file_compression::{dictionary::DEFAULT_DICTIONARY_SIZE, DictionaryStore};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tokio::task;
+
+use utoipa::ToSchema;
+
+use crate::handlers::{ApiError, ErrorResponse};
+use crate::state::AppState;
+
+const DEFAULT_MAX_FILES: usize = 1000;
+const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024;
+
+#[derive(Deserialize)]
+pub struct TrainParams {
+    /// Maximum number of uploaded files to sample.
+    max_files: Option<usize>,
+    /// Only files up to this many bytes are sampled.
+    max_file_size: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DictionaryResponse {
+    pub version: u32,
+    pub sample_count: usize,
+    pub size: usize,
+}
+
+#[utoipa::path(
+    post,
+    path = "/compressor/dictionary",
+    params(
+        ("max_files" = Option<usize>, Query, description = "Maximum number of uploaded files to sample (default 1000)"),
+        ("max_file_size" = Option<u64>, Query, description = "Only sample files up to this many bytes (default 65536)")
+    ),
+    responses(
+        (status = 200, description = "Dictionary trained", body = DictionaryResponse),
+        (status = 400, description = "No uploaded files small enough to sample", body = ErrorResponse),
+        (status = 500, description = "Training failed", body = ErrorResponse)
+    ),
+    tag = "file-service"
+)]
+
+pub async fn train_dictionary(
+    params: Result<Query<TrainParams>, QueryRejection>,
+    State(AppState { config, .. }): State<AppState>,
+) -> Result<Json<DictionaryResponse>, ApiError> {
+    let Query(params) = params?;
+    let max_files = params.max_files.unwrap_or(DEFAULT_MAX_FILES);
+    let max_file_size = params.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE);
+
+    let samples = sample_uploads(&config.storage.uploads_dir, max_files, max_file_size)
+        .map_err(|e| ApiError::internal(format!("Failed to read uploads: {}", e)))?;
+    if samples.is_empty() {
+        return Err(ApiError::bad_request(
+            "No uploaded files small enough to train a dictionary",
+        ));
+    }
+
+    let sample_count = samples.len();
+    let trained = task::spawn_blocking(move || {
+        DictionaryStore::new(&config.storage.dictionaries_dir)
+            .train(&samples, DEFAULT_DICTIONARY_SIZE)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Training task failed: {}", e)))?
+    .map_err(|e| ApiError::internal(format!("Failed to train dictionary: {}", e)))?;
+
+    Ok(Json(DictionaryResponse {
+        version: trained.version(),
+        sample_count,
+        size: trained.data().len(),
+    }))
+}
+
+/// Picks up to `max_files` of the most recent uploads no larger than
+/// `max_file_size`.
+fn sample_uploads(
+    uploads_dir: &Path,
+    max_files: usize,
+    max_file_size: u64,
+) -> io::Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(uploads_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut candidates = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && metadata.len() > 0 && metadata.len() <= max_file_size {
+            candidates.push((metadata.modified()?, entry.path()));
+        }
+    }
+
+    candidates.sort_unstable_by(|a, b| b.cmp(a));
+    candidates.truncate(max_files);
+    Ok(candidates.into_iter().map(|(_, path)| path).collect())
+}
@@ -0,0 +1,75 @@
+use axum::{
+    extract::{Request, State},
+    handler::HandlerWithoutStateExt,
+    http::{header::CONTENT_DISPOSITION, HeaderValue},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Router,
+};
+use percent_encoding::percent_decode_str;
+use std::path::Path;
+use tower_http::services::ServeDir;
+
+use crate::handlers::not_found;
+use crate::naming::content_disposition;
+use crate::state::AppState;
+
+/// Serves the files in `dir` under `/files`, named after the uploaded files
+/// by [`name_download`]. Missing files get the JSON 404 of other routes.
+pub fn serve_files(dir: &Path, state: AppState) -> Router<AppState> {
+    Router::new()
+        .nest_service(
+            "/files",
+            ServeDir::new(dir).not_found_service(not_found.into_service()),
+        )
+        .layer(middleware::from_fn_with_state(state, name_download))
+}
+
+/// Names downloaded uploads and outputs after the file the client uploaded,
+/// keeping the suffix of the stored name, e.g. `report.pdf.gz.001` for
+/// `<key>.gz.001`. Paths with a segment starting with `.` are not served,
+/// since stored names never have one.
+pub async fn name_download(
+    State(AppState { pool, .. }): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let hidden = request.uri().path().split('/').any(|segment| {
+        percent_decode_str(segment)
+            .decode_utf8_lossy()
+            .starts_with('.')
+    });
+    if hidden {
+        return not_found().await.into_response();
+    }
+
+    let stored_name = request
+        .uri()
+        .path()
+        .rsplit('/')
+        .next()
+        .map(|name| percent_decode_str(name).decode_utf8_lossy().into_owned())
+        .unwrap_or_default();
+    let mut response = next.run(request).await;
+    if !response.status().is_success() || stored_name.is_empty() {
+        return response;
+    }
+
+    let task = sqlx::query_as::<_, (String, Option<String>)>(
+        "SELECT file_name, original_name FROM compression_tasks
+         WHERE file_name = $1 OR starts_with($1, file_name || '.')
+         ORDER BY length(file_name) DESC
+         LIMIT 1",
+    )
+    .bind(&stored_name)
+    .fetch_optional(&pool)
+    .await;
+
+    if let Ok(Some((file_name, Some(original_name)))) = task {
+        let download_name = format!("{}{}", original_name, &stored_name[file_name.len()..]);
+        if let Ok(value) = HeaderValue::from_str(&content_disposition(&download_name)) {
+            response.headers_mut().insert(CONTENT_DISPOSITION, value);
+        }
+    }
+    response
+}
@@ -1,8 +1,108 @@
 pub mod check;
 pub mod compress_file;
+pub mod dictionary;
+pub mod downloads;
+pub mod transcode;
+pub mod tus;
 pub mod upload_file;
+pub mod volumes;
 
-// Only re-export what's actually needed
-pub use check::{check_status, ErrorResponse, StatusResponse};
-pub use compress_file::{compress_all_files, CompressionResponse};
-pub use upload_file::{upload_files, UploadResponse};
+use axum::{
+    extract::{
+        multipart::MultipartRejection,
+        rejection::{PathRejection, QueryRejection},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use file_compression::CompressionError;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub message: String,
+}
+
+/// A failed request, answered with `status` and an [`ErrorResponse`].
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+
+    pub fn database(error: sqlx::Error) -> Self {
+        Self::internal(format!("Database error: {}", error))
+    }
+}
+
+impl From<CompressionError> for ApiError {
+    fn from(error: CompressionError) -> Self {
+        Self::new(error_status(&error), error.to_string())
+    }
+}
+
+/// Rejections of malformed requests, answered in JSON like other errors.
+macro_rules! from_rejection {
+    ($($rejection:ty),*) => {$(
+        impl From<$rejection> for ApiError {
+            fn from(rejection: $rejection) -> Self {
+                Self::new(rejection.status(), rejection.body_text())
+            }
+        }
+    )*};
+}
+
+from_rejection!(MultipartRejection, PathRejection, QueryRejection);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (
+            self.status,
+            Json(ErrorResponse {
+                message: self.message,
+            }),
+        )
+            .into_response()
+    }
+}
+
+/// Answers requests that match no route.
+pub async fn not_found() -> ApiError {
+    ApiError::not_found("Resource not found")
+}
+
+/// HTTP status for a compression error surfaced to the client.
+pub fn error_status(error: &CompressionError) -> StatusCode {
+    match error {
+        CompressionError::InvalidOption { .. } => StatusCode::BAD_REQUEST,
+        CompressionError::LimitExceeded(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        CompressionError::Cancelled => StatusCode::CONFLICT,
+        CompressionError::Read { .. }
+        | CompressionError::Write { .. }
+        | CompressionError::Codec { .. }
+        | CompressionError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
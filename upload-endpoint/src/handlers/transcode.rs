@@ -0,0 +1,269 @@
+use axum::{
+    extract::{rejection::QueryRejection, Query, State},
+    Json,
+};
+use file_compression::{
+    codec::Codec, dictionary::ZSTD_DICT, transcode_file, CompressionOptions, DecompressionLimits,
+    DictionaryStore,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::{fs, io, sync::Arc};
+use tokio::task;
+use utoipa::ToSchema;
+
+use crate::config::StorageConfig;
+use crate::external_codecs::ExternalCodecs;
+use crate::handlers::{ApiError, ErrorResponse};
+use crate::state::AppState;
+
+/// A completed task and what its output was compressed with.
+#[derive(FromRow)]
+struct CompletedTask {
+    id: i32,
+    file_name: String,
+    algorithm: String,
+    original_sha256: Option<String>,
+    dictionary_version: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct TranscodeParams {
+    /// Codec to move outputs to, e.g. `zstd`.
+    algorithm: String,
+    /// Level for the new codec. Defaults to the codec's default level.
+    compression_level: Option<u32>,
+    /// Only transcode this task instead of every completed one.
+    task_id: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TranscodeResponse {
+    pub message: String,
+    pub file_count: usize,
+    /// Codec the outputs are moved to.
+    pub algorithm: String,
+    pub compression_level: u32,
+    /// Tasks that started transcoding.
+    pub task_ids: Vec<i32>,
+}
+
+/// New settings of a task whose output was replaced.
+struct Transcoded {
+    compression_level: u32,
+    compressed_sha256: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/compressor/transcode",
+    params(
+        ("algorithm" = String, Query, description = "Codec to recompress completed outputs with, e.g. zstd"),
+        ("compression_level" = Option<u32>, Query, description = "Level for the new codec"),
+        ("task_id" = Option<i32>, Query, description = "Only transcode this task")
+    ),
+    responses(
+        (status = 200, description = "Transcoding started", body = TranscodeResponse),
+        (status = 400, description = "Unsupported algorithm or level", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "file-service"
+)]
+
+pub async fn transcode_completed(
+    params: Result<Query<TranscodeParams>, QueryRejection>,
+    State(AppState {
+        config,
+        pool,
+        codecs,
+        ..
+    }): State<AppState>,
+) -> Result<Json<TranscodeResponse>, ApiError> {
+    let Query(params) = params?;
+    let mut builder =
+        codecs.with_codec_name(CompressionOptions::builder(), params.algorithm.trim());
+    if let Some(level) = params.compression_level {
+        builder = builder.level(level);
+    }
+    let options = builder.build()?;
+    // Background tasks find the codec again by its name
+    let (target, level) = (options.codec().name(), options.level());
+
+    // Stored files have nothing to transcode, and outputs already in the
+    // target codec or split into volumes are left alone. Tasks are claimed
+    // in the same statement, so overlapping requests never pick the same one.
+    let tasks = sqlx::query_as::<_, CompletedTask>(
+        "UPDATE compression_tasks SET status = 'transcoding'
+         WHERE status = 'completed' AND algorithm NOT IN ('none', $1) AND volume_count IS NULL
+           AND ($2::INTEGER IS NULL OR id = $2)
+         RETURNING id, file_name, algorithm, original_sha256, dictionary_version",
+    )
+    .bind(target)
+    .bind(params.task_id)
+    .fetch_all(&pool)
+    .await
+    .map_err(ApiError::database)?;
+
+    let task_count = tasks.len();
+    let task_ids: Vec<i32> = tasks.iter().map(|task| task.id).collect();
+    if task_count == 0 {
+        return Ok(Json(TranscodeResponse {
+            message: "No files to transcode".to_string(),
+            file_count: task_count,
+            algorithm: target.to_string(),
+            compression_level: level,
+            task_ids,
+        }));
+    }
+
+    for completed in tasks {
+        let pool = pool.clone();
+        let config = Arc::clone(&config);
+        let codecs = Arc::clone(&codecs);
+
+        task::spawn(async move {
+            let task_id = completed.id;
+            let result = task::spawn_blocking(move || {
+                transcode_task(&config.storage, &codecs, &completed, target, level)
+            })
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)));
+
+            // The original output stays in place unless the new one is
+            // smaller, so the task is completed either way. A failed
+            // transcode is recorded so it is not mistaken for a kept output.
+            let update =
+                match result {
+                    Ok(Some(transcoded)) => {
+                        sqlx::query(
+                            "UPDATE compression_tasks
+                         SET status = 'completed', algorithm = $1, compression_level = $2,
+                             compressed_sha256 = $3, dictionary_version = NULL,
+                             failure_reason = NULL
+                         WHERE id = $4",
+                        )
+                        .bind(target)
+                        .bind(transcoded.compression_level as i32)
+                        .bind(transcoded.compressed_sha256)
+                        .bind(task_id)
+                        .execute(&pool)
+                        .await
+                    }
+                    Ok(None) => sqlx::query(
+                        "UPDATE compression_tasks SET status = 'completed', failure_reason = NULL
+                         WHERE id = $1",
+                    )
+                    .bind(task_id)
+                    .execute(&pool)
+                    .await,
+                    Err(e) => {
+                        eprintln!("Failed to transcode task {} to {}: {}", task_id, target, e);
+                        sqlx::query(
+                            "UPDATE compression_tasks SET status = 'completed', failure_reason = $1
+                         WHERE id = $2",
+                        )
+                        .bind(format!("Transcoding to {} failed: {}", target, e))
+                        .bind(task_id)
+                        .execute(&pool)
+                        .await
+                    }
+                };
+            if let Err(e) = update {
+                eprintln!(
+                    "Failed to record transcode result of task {}: {}",
+                    task_id, e
+                );
+            }
+        });
+    }
+
+    Ok(Json(TranscodeResponse {
+        message: format!(
+            "Started transcoding {} files to {} in background",
+            task_count, target
+        ),
+        file_count: task_count,
+        algorithm: target.to_string(),
+        compression_level: level,
+        task_ids,
+    }))
+}
+
+/// Transcodes one task's output, keeping whichever of the old and new
+/// outputs is smaller. Returns the new settings if the output was replaced.
+fn transcode_task(
+    storage: &StorageConfig,
+    codecs: &ExternalCodecs,
+    completed: &CompletedTask,
+    target: &str,
+    level: u32,
+) -> io::Result<Option<Transcoded>> {
+    let unsupported = |algorithm: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unsupported compression algorithm: {}", algorithm),
+        )
+    };
+    let target = codecs
+        .codec_by_name(target)
+        .ok_or_else(|| unsupported(target))?;
+    let dictionary = match completed.dictionary_version {
+        Some(version) => {
+            Some(DictionaryStore::new(&storage.dictionaries_dir).load(version as u32)?)
+        }
+        None => None,
+    };
+    let source: &dyn Codec = match &dictionary {
+        Some(dictionary) => dictionary,
+        None if completed.algorithm == ZSTD_DICT => codecs.codec_by_name("zstd").unwrap(),
+        None => codecs
+            .codec_by_name(&completed.algorithm)
+            .ok_or_else(|| unsupported(&completed.algorithm))?,
+    };
+
+    let source_path =
+        storage.compressed_path(&format!("{}.{}", completed.file_name, source.extension()));
+    let target_path =
+        storage.compressed_path(&format!("{}.{}", completed.file_name, target.extension()));
+    // Written next to the target and renamed into place, since the source
+    // and target may share an extension
+    let partial_path = storage.compressed_path(&format!(
+        "{}.{}.part",
+        completed.file_name,
+        target.extension()
+    ));
+
+    let report = transcode_file(
+        source,
+        target,
+        &source_path,
+        &partial_path,
+        level,
+        &DecompressionLimits::unlimited(),
+    )?;
+
+    let unchanged = completed
+        .original_sha256
+        .as_deref()
+        .is_none_or(|sha256| sha256 == report.output.original_sha256);
+    if !unchanged || report.output.report.bytes_written >= report.source_bytes {
+        fs::remove_file(&partial_path)?;
+        if !unchanged {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} does not decode to the uploaded file", source_path),
+            ));
+        }
+        return Ok(None);
+    }
+
+    fs::rename(&partial_path, &target_path)?;
+    if source_path != target_path {
+        fs::remove_file(&source_path)?;
+    }
+
+    Ok(Some(Transcoded {
+        compression_level: level,
+        compressed_sha256: report.output.compressed_sha256,
+    }))
+}
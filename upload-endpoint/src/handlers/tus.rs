@@ -0,0 +1,714 @@
+use axum::{
+    body::Body,
+    extract::{rejection::PathRejection, OriginalUri, Path, Request, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_TYPE, LOCATION},
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{head, options},
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures_util::StreamExt;
+use sha1::Sha1;
+use sha2::{digest::DynDigest, Sha256};
+use sqlx::{FromRow, PgPool};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, SeekFrom},
+    path::Path as FsPath,
+    sync::{Arc, Mutex},
+};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use crate::config::StorageConfig;
+use crate::handlers::upload_file::{register_upload, resolve_settings};
+use crate::handlers::{ApiError, ErrorResponse};
+use crate::naming::{new_storage_key, normalize_file_name};
+use crate::state::AppState;
+
+/// Version of the tus protocol spoken, the only one accepted.
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination,checksum";
+const CHECKSUM_ALGORITHMS: &str = "sha1,sha256";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION_HEADER: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const TUS_CHECKSUM_ALGORITHM: HeaderName = HeaderName::from_static("tus-checksum-algorithm");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const UPLOAD_CHECKSUM: HeaderName = HeaderName::from_static("upload-checksum");
+/// Compression task created once the upload is complete.
+const TASK_ID: HeaderName = HeaderName::from_static("compression-task-id");
+
+/// Metadata keys read from `Upload-Metadata`; the settings match the
+/// multipart upload fields.
+const FILENAME_KEY: &str = "filename";
+const ALGORITHM_KEY: &str = "algorithm";
+const COMPRESSION_LEVEL_KEY: &str = "compression_level";
+
+/// An upload in progress, as persisted in `tus_uploads`.
+#[derive(FromRow)]
+struct TusUpload {
+    id: String,
+    upload_length: i64,
+    upload_offset: i64,
+    metadata: Option<String>,
+    original_name: String,
+    algorithm: String,
+    compression_level: i32,
+    task_id: Option<i32>,
+}
+
+/// Uploads a request is currently writing to or terminating, so that two
+/// requests never change the same upload at once.
+#[derive(Clone, Default)]
+pub struct ActiveUploads(Arc<Mutex<HashSet<String>>>);
+
+impl ActiveUploads {
+    fn lock(&self, id: &str) -> Result<UploadLock, ApiError> {
+        if !self.0.lock().unwrap().insert(id.to_string()) {
+            return Err(ApiError::new(
+                StatusCode::LOCKED,
+                format!("Upload {} is being changed by another request", id),
+            ));
+        }
+        Ok(UploadLock {
+            uploads: self.clone(),
+            id: id.to_string(),
+        })
+    }
+}
+
+/// Releases an upload when dropped.
+struct UploadLock {
+    uploads: ActiveUploads,
+    id: String,
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        self.uploads.0.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Routes of the tus resumable upload protocol: the core protocol with the
+/// creation, termination and checksum extensions.
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", options(tus_options).post(create_upload))
+        .route(
+            "/{upload_id}",
+            head(upload_offset)
+                .patch(append_upload)
+                .delete(terminate_upload),
+        )
+        .layer(middleware::from_fn(tus_resumable))
+}
+
+/// Rejects requests for another protocol version and marks every response
+/// with the version spoken. `OPTIONS` is exempt, it is how clients find out.
+async fn tus_resumable(request: Request, next: Next) -> Response {
+    let supported = request.method() == Method::OPTIONS
+        || request
+            .headers()
+            .get(TUS_RESUMABLE)
+            .is_some_and(|version| version == TUS_VERSION);
+
+    let mut response = if supported {
+        next.run(request).await
+    } else {
+        let mut response = ApiError::new(
+            StatusCode::PRECONDITION_FAILED,
+            format!("Tus-Resumable: {} is required", TUS_VERSION),
+        )
+        .into_response();
+        response
+            .headers_mut()
+            .insert(TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION));
+        response
+    };
+    response
+        .headers_mut()
+        .insert(TUS_RESUMABLE, HeaderValue::from_static(TUS_VERSION));
+    response
+}
+
+#[utoipa::path(
+    options,
+    path = "/uploader/tus",
+    responses(
+        (status = 204, description = "Supported tus version, extensions, checksum algorithms and maximum upload size, in the Tus-* headers")
+    ),
+    tag = "file-service"
+)]
+
+pub async fn tus_options(State(AppState { config, .. }): State<AppState>) -> impl IntoResponse {
+    (
+        StatusCode::NO_CONTENT,
+        [
+            (TUS_VERSION_HEADER, HeaderValue::from_static(TUS_VERSION)),
+            (TUS_EXTENSION, HeaderValue::from_static(TUS_EXTENSIONS)),
+            (
+                TUS_MAX_SIZE,
+                HeaderValue::from(config.uploads.max_file_size),
+            ),
+            (
+                TUS_CHECKSUM_ALGORITHM,
+                HeaderValue::from_static(CHECKSUM_ALGORITHMS),
+            ),
+        ],
+    )
+}
+
+#[utoipa::path(
+    post,
+    path = "/uploader/tus",
+    params(
+        ("Tus-Resumable" = String, Header, description = "Protocol version, 1.0.0"),
+        ("Upload-Length" = u64, Header, description = "Size of the whole file in bytes"),
+        ("Upload-Metadata" = String, Header, description = "Comma-separated keys and base64 values: `filename` (required), `algorithm` and `compression_level` as for multipart uploads")
+    ),
+    responses(
+        (status = 201, description = "Upload created, its URL is in the Location header"),
+        (status = 400, description = "Missing length or file name, or invalid compression settings", body = ErrorResponse),
+        (status = 412, description = "Unsupported tus version", body = ErrorResponse),
+        (status = 413, description = "The file is larger than the configured limit", body = ErrorResponse)
+    ),
+    tag = "file-service"
+)]
+
+pub async fn create_upload(
+    State(AppState {
+        config,
+        pool,
+        codecs,
+        ..
+    }): State<AppState>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let upload_length = number_header(&headers, &UPLOAD_LENGTH)?
+        .ok_or_else(|| ApiError::bad_request("Upload-Length is required"))?;
+    if upload_length > config.uploads.max_file_size {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Upload exceeds the limit of {} bytes per file",
+                config.uploads.max_file_size
+            ),
+        ));
+    }
+
+    let raw_metadata = headers
+        .get(UPLOAD_METADATA)
+        .map(|value| value.to_str())
+        .transpose()
+        .map_err(|_| ApiError::bad_request("Upload-Metadata is not valid ASCII"))?;
+    let metadata = parse_metadata(raw_metadata.unwrap_or_default())?;
+    let original_name = metadata
+        .get(FILENAME_KEY)
+        .ok_or_else(|| ApiError::bad_request("Upload-Metadata must include a filename"))
+        .and_then(|name| {
+            normalize_file_name(name)
+                .map_err(|e| ApiError::bad_request(format!("Rejected file name: {}", e)))
+        })?;
+    let (algorithm, level) = resolve_settings(
+        &codecs,
+        metadata.get(ALGORITHM_KEY).map(String::as_str),
+        metadata.get(COMPRESSION_LEVEL_KEY).map(String::as_str),
+    )?;
+
+    let upload_id = new_storage_key();
+    let data_path = config.storage.tus_dir.join(&upload_id);
+    tokio::fs::File::create(&data_path)
+        .await
+        .map_err(|e| ApiError::internal(format!("Failed to create upload: {}", e)))?;
+
+    let upload = sqlx::query_as::<_, TusUpload>(
+        "INSERT INTO tus_uploads (id, upload_length, metadata, original_name, algorithm, compression_level)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         RETURNING id, upload_length, upload_offset, metadata, original_name, algorithm,
+                   compression_level, task_id",
+    )
+    .bind(&upload_id)
+    .bind(upload_length as i64)
+    .bind(raw_metadata)
+    .bind(&original_name)
+    .bind(algorithm)
+    .bind(level as i32)
+    .fetch_one(&pool)
+    .await;
+    let upload = match upload {
+        Ok(upload) => upload,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&data_path).await;
+            return Err(ApiError::database(e));
+        }
+    };
+
+    let location = format!("{}/{}", uri.path().trim_end_matches('/'), upload_id);
+    let mut response = (StatusCode::CREATED, [(LOCATION, location)]).into_response();
+    // An empty file is complete as soon as it exists
+    if upload_length == 0 {
+        let task_id = finish_upload(&config.storage, &pool, &upload).await?;
+        response.headers_mut().insert(TASK_ID, task_id.into());
+    }
+    Ok(response)
+}
+
+#[utoipa::path(
+    head,
+    path = "/uploader/tus/{upload_id}",
+    params(
+        ("upload_id" = String, Path, description = "Upload ID from the Location of the creation response"),
+        ("Tus-Resumable" = String, Header, description = "Protocol version, 1.0.0")
+    ),
+    responses(
+        (status = 200, description = "Bytes received so far in Upload-Offset, and the compression task in Compression-Task-Id once complete"),
+        (status = 404, description = "Upload not found")
+    ),
+    tag = "file-service"
+)]
+
+pub async fn upload_offset(
+    upload_id: Result<Path<String>, PathRejection>,
+    State(AppState { pool, .. }): State<AppState>,
+) -> Result<HeaderMap, ApiError> {
+    let Path(upload_id) = upload_id?;
+    let upload = find_upload(&pool, &upload_id).await?;
+
+    let mut headers = offset_headers(&upload);
+    headers.insert(UPLOAD_LENGTH, upload.upload_length.into());
+    if let Some(metadata) = upload
+        .metadata
+        .as_deref()
+        .and_then(|metadata| HeaderValue::from_str(metadata).ok())
+    {
+        headers.insert(UPLOAD_METADATA, metadata);
+    }
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    Ok(headers)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/uploader/tus/{upload_id}",
+    params(
+        ("upload_id" = String, Path, description = "Upload ID from the Location of the creation response"),
+        ("Tus-Resumable" = String, Header, description = "Protocol version, 1.0.0"),
+        ("Upload-Offset" = u64, Header, description = "Offset the body continues the upload at, as returned by HEAD"),
+        ("Upload-Checksum" = Option<String>, Header, description = "Algorithm and base64 digest of the body, e.g. `sha1 <digest>`")
+    ),
+    request_body(
+        content = Vec<u8>,
+        description = "The next bytes of the file",
+        content_type = "application/offset+octet-stream"
+    ),
+    responses(
+        (status = 204, description = "Bytes stored, the new offset is in Upload-Offset and the compression task in Compression-Task-Id once complete"),
+        (status = 400, description = "Missing offset or unsupported checksum algorithm", body = ErrorResponse),
+        (status = 404, description = "Upload not found", body = ErrorResponse),
+        (status = 409, description = "The offset does not match the bytes received", body = ErrorResponse),
+        (status = 413, description = "The body goes past the upload length", body = ErrorResponse),
+        (status = 415, description = "Wrong Content-Type", body = ErrorResponse),
+        (status = 423, description = "Another request is writing to the upload", body = ErrorResponse),
+        (status = 460, description = "The body does not match Upload-Checksum and was discarded", body = ErrorResponse)
+    ),
+    tag = "file-service"
+)]
+
+pub async fn append_upload(
+    upload_id: Result<Path<String>, PathRejection>,
+    State(AppState {
+        config,
+        pool,
+        tus_uploads,
+        ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<(StatusCode, HeaderMap), ApiError> {
+    let Path(upload_id) = upload_id?;
+    if headers
+        .get(CONTENT_TYPE)
+        .is_none_or(|content_type| content_type != OFFSET_CONTENT_TYPE)
+    {
+        return Err(ApiError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("Content-Type must be {}", OFFSET_CONTENT_TYPE),
+        ));
+    }
+    let offset = number_header(&headers, &UPLOAD_OFFSET)?
+        .ok_or_else(|| ApiError::bad_request("Upload-Offset is required"))?;
+    let checksum = headers
+        .get(UPLOAD_CHECKSUM)
+        .map(parse_checksum)
+        .transpose()?;
+
+    let _lock = tus_uploads.lock(&upload_id)?;
+    let mut upload = find_upload(&pool, &upload_id).await?;
+    if offset != upload.upload_offset as u64 {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "Upload-Offset is {} but {} bytes have been received",
+                offset, upload.upload_offset
+            ),
+        ));
+    }
+
+    let data_path = config.storage.tus_dir.join(&upload.id);
+    let written = if offset < upload.upload_length as u64 {
+        write_body(&data_path, &upload, body, checksum).await?
+    } else {
+        0
+    };
+    if written > 0 {
+        upload.upload_offset += written as i64;
+        sqlx::query("UPDATE tus_uploads SET upload_offset = $1 WHERE id = $2")
+            .bind(upload.upload_offset)
+            .bind(&upload.id)
+            .execute(&pool)
+            .await
+            .map_err(ApiError::database)?;
+    }
+
+    // Also retried here if a previous request stored the last bytes but
+    // failed to queue the file
+    if upload.upload_offset == upload.upload_length && upload.task_id.is_none() {
+        upload.task_id = Some(finish_upload(&config.storage, &pool, &upload).await?);
+    }
+    Ok((StatusCode::NO_CONTENT, offset_headers(&upload)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/uploader/tus/{upload_id}",
+    params(
+        ("upload_id" = String, Path, description = "Upload ID from the Location of the creation response"),
+        ("Tus-Resumable" = String, Header, description = "Protocol version, 1.0.0")
+    ),
+    responses(
+        (status = 204, description = "Upload and its data deleted"),
+        (status = 404, description = "Upload not found", body = ErrorResponse),
+        (status = 409, description = "The upload is complete and queued for compression", body = ErrorResponse),
+        (status = 423, description = "Another request is writing to the upload", body = ErrorResponse)
+    ),
+    tag = "file-service"
+)]
+
+pub async fn terminate_upload(
+    upload_id: Result<Path<String>, PathRejection>,
+    State(AppState {
+        config,
+        pool,
+        tus_uploads,
+        ..
+    }): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    let Path(upload_id) = upload_id?;
+    let _lock = tus_uploads.lock(&upload_id)?;
+    let upload = find_upload(&pool, &upload_id).await?;
+    if let Some(task_id) = upload.task_id {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "Upload {} is complete and belongs to task {}",
+                upload.id, task_id
+            ),
+        ));
+    }
+
+    sqlx::query("DELETE FROM tus_uploads WHERE id = $1")
+        .bind(&upload.id)
+        .execute(&pool)
+        .await
+        .map_err(ApiError::database)?;
+    match tokio::fs::remove_file(config.storage.tus_dir.join(&upload.id)).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(ApiError::internal(format!(
+                "Failed to delete upload data: {}",
+                e
+            )))
+        }
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn find_upload(pool: &PgPool, upload_id: &str) -> Result<TusUpload, ApiError> {
+    sqlx::query_as::<_, TusUpload>(
+        "SELECT id, upload_length, upload_offset, metadata, original_name, algorithm,
+                compression_level, task_id
+         FROM tus_uploads WHERE id = $1",
+    )
+    .bind(upload_id)
+    .fetch_optional(pool)
+    .await
+    .map_err(ApiError::database)?
+    .ok_or_else(|| ApiError::not_found(format!("Upload {} not found", upload_id)))
+}
+
+/// `Upload-Offset`, and the compression task once the upload is complete.
+fn offset_headers(upload: &TusUpload) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(UPLOAD_OFFSET, upload.upload_offset.into());
+    if let Some(task_id) = upload.task_id {
+        headers.insert(TASK_ID, task_id.into());
+    }
+    headers
+}
+
+/// Appends `body` to the upload's data at its current offset, returning the
+/// number of bytes stored.
+///
+/// Bytes past the recorded offset, left by a request that was cut off before
+/// the offset was saved, are overwritten. If the client disconnects, what
+/// arrived is kept so it can resume from there, unless a checksum was given:
+/// a body with a checksum is stored whole or not at all.
+async fn write_body(
+    data_path: &FsPath,
+    upload: &TusUpload,
+    body: Body,
+    checksum: Option<(Box<dyn DynDigest + Send>, Vec<u8>)>,
+) -> Result<u64, ApiError> {
+    let failed = |e: io::Error| ApiError::internal(format!("Failed to store upload data: {}", e));
+    let start = upload.upload_offset as u64;
+    let remaining = (upload.upload_length - upload.upload_offset) as u64;
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(data_path)
+        .await
+        .map_err(failed)?;
+    file.set_len(start).await.map_err(failed)?;
+    file.seek(SeekFrom::Start(start)).await.map_err(failed)?;
+
+    let (mut hasher, expected) = match checksum {
+        Some((hasher, expected)) => (Some(hasher), Some(expected)),
+        None => (None, None),
+    };
+    let mut written = 0;
+    let mut cut_off = false;
+    let mut stream = body.into_data_stream();
+    let result = async {
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| {
+                cut_off = true;
+                ApiError::bad_request(format!("Failed to read upload data: {}", e))
+            })?;
+            if written + chunk.len() as u64 > remaining {
+                return Err(ApiError::new(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "Body goes past the upload length of {} bytes",
+                        upload.upload_length
+                    ),
+                ));
+            }
+            file.write_all(&chunk).await.map_err(failed)?;
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&chunk);
+            }
+            written += chunk.len() as u64;
+        }
+        if let (Some(hasher), Some(expected)) = (hasher.take(), expected.as_ref()) {
+            if *hasher.finalize() != **expected {
+                return Err(ApiError::new(
+                    StatusCode::from_u16(460).unwrap(),
+                    "Body does not match Upload-Checksum",
+                ));
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    let keep = match result {
+        Ok(()) => written,
+        Err(_) if cut_off && expected.is_none() => written,
+        Err(_) => 0,
+    };
+    file.set_len(start + keep).await.map_err(failed)?;
+    file.sync_all().await.map_err(failed)?;
+
+    match result {
+        Err(e) if keep == 0 => Err(e),
+        _ => Ok(keep),
+    }
+}
+
+/// Moves a complete upload into the uploads directory and queues it for
+/// compression, as a multipart upload would be, returning the task ID.
+/// Safe to retry if it failed after the file was moved.
+async fn finish_upload(
+    storage: &StorageConfig,
+    pool: &PgPool,
+    upload: &TusUpload,
+) -> Result<i32, ApiError> {
+    let save_path = storage.upload_path(&upload.id);
+    match tokio::fs::rename(storage.tus_dir.join(&upload.id), &save_path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound && FsPath::new(&save_path).exists() => {}
+        Err(e) => {
+            return Err(ApiError::internal(format!(
+                "Failed to save {}: {}",
+                upload.original_name, e
+            )))
+        }
+    }
+
+    let mut transaction = pool.begin().await.map_err(ApiError::database)?;
+    let task_id = register_upload(
+        &mut *transaction,
+        &upload.id,
+        &upload.original_name,
+        &upload.algorithm,
+        upload.compression_level as u32,
+    )
+    .await
+    .map_err(ApiError::database)?;
+    sqlx::query("UPDATE tus_uploads SET task_id = $1 WHERE id = $2")
+        .bind(task_id)
+        .bind(&upload.id)
+        .execute(&mut *transaction)
+        .await
+        .map_err(ApiError::database)?;
+    transaction.commit().await.map_err(ApiError::database)?;
+    Ok(task_id)
+}
+
+/// Reads a header holding a non-negative number.
+fn number_header(headers: &HeaderMap, name: &HeaderName) -> Result<Option<u64>, ApiError> {
+    headers
+        .get(name)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| ApiError::bad_request(format!("{} must be a number", name)))
+        })
+        .transpose()
+}
+
+/// Parses `Upload-Metadata`: comma-separated pairs of a key and a base64
+/// value, where the value may be left out.
+fn parse_metadata(raw: &str) -> Result<HashMap<String, String>, ApiError> {
+    let mut metadata = HashMap::new();
+    for pair in raw
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+        let value = STANDARD
+            .decode(value.trim())
+            .ok()
+            .and_then(|value| String::from_utf8(value).ok())
+            .ok_or_else(|| {
+                ApiError::bad_request(format!(
+                    "Upload-Metadata value of {} is not base64-encoded UTF-8",
+                    key
+                ))
+            })?;
+        if metadata.insert(key.to_string(), value).is_some() {
+            return Err(ApiError::bad_request(format!(
+                "Upload-Metadata has {} more than once",
+                key
+            )));
+        }
+    }
+    Ok(metadata)
+}
+
+/// Parses `Upload-Checksum` into a hasher for its algorithm and the
+/// expected digest.
+fn parse_checksum(value: &HeaderValue) -> Result<(Box<dyn DynDigest + Send>, Vec<u8>), ApiError> {
+    let invalid =
+        || ApiError::bad_request("Upload-Checksum must be an algorithm and a base64 digest");
+    let (algorithm, digest) = value
+        .to_str()
+        .ok()
+        .and_then(|value| value.split_once(' '))
+        .ok_or_else(invalid)?;
+    let hasher: Box<dyn DynDigest + Send> = match algorithm {
+        "sha1" => Box::new(Sha1::default()),
+        "sha256" => Box::new(Sha256::default()),
+        _ => {
+            return Err(ApiError::bad_request(format!(
+                "Unsupported checksum algorithm {}, expected one of {}",
+                algorithm, CHECKSUM_ALGORITHMS
+            )))
+        }
+    };
+    let digest = STANDARD.decode(digest.trim()).map_err(|_| invalid())?;
+    Ok((hasher, digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_is_decoded() {
+        let metadata =
+            parse_metadata("filename csOpc3Vtw6kucGRm, algorithm enN0ZA==,is_confidential")
+                .unwrap();
+        assert_eq!(metadata.len(), 3);
+        assert_eq!(metadata[FILENAME_KEY], "résumé.pdf");
+        assert_eq!(metadata[ALGORITHM_KEY], "zstd");
+        // Keys may come without a value
+        assert_eq!(metadata["is_confidential"], "");
+        assert!(parse_metadata("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_metadata_is_rejected() {
+        for raw in [
+            "filename not*base64",
+            "filename /w==",
+            "filename enN0ZA==,filename enN0ZA==",
+        ] {
+            let error = parse_metadata(raw).unwrap_err();
+            assert_eq!(error.status, StatusCode::BAD_REQUEST, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn test_checksum_is_parsed() {
+        for (header, digest) in [
+            (
+                "sha1 qvTGHdzF6KLavt4PO0gs2a6pQ00=",
+                "qvTGHdzF6KLavt4PO0gs2a6pQ00=",
+            ),
+            (
+                "sha256 LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=",
+                "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=",
+            ),
+        ] {
+            let (mut hasher, expected) = parse_checksum(&HeaderValue::from_static(header)).unwrap();
+            assert_eq!(expected, STANDARD.decode(digest).unwrap());
+            hasher.update(b"hello");
+            assert_eq!(&*hasher.finalize(), expected.as_slice());
+        }
+    }
+
+    #[test]
+    fn test_invalid_checksum_is_rejected() {
+        for header in ["sha256", "md5 XUFAKrxLKna5cZ2REBfFkg==", "sha1 not*base64"] {
+            let error = parse_checksum(&HeaderValue::from_static(header))
+                .err()
+                .unwrap();
+            assert_eq!(error.status, StatusCode::BAD_REQUEST, "{}", header);
+        }
+    }
+}
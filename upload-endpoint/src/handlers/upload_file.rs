@@ -1,27 +1,55 @@
 use axum::{
-    extract::{Extension, Multipart},
+    extract::{
+        multipart::{Field, MultipartError, MultipartRejection},
+        Multipart, State,
+    },
     http::StatusCode,
-    response::IntoResponse,
+    Json,
 };
 
-use dotenv::{dotenv, var};
-use sqlx::Row;
-use sqlx::{postgres::PgQueryResult, PgPool};
-use std::{
-    fs::{self, File},
-    io::{self, Write},
-    path::PathBuf,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use file_compression::{auto::AUTO, dictionary::ZSTD_DICT, CompressionError, CompressionOptions};
+use serde::Serialize;
+use sqlx::PgExecutor;
+use std::fs;
+use tokio::io::AsyncWriteExt;
 
 use utoipa::ToSchema;
 
-#[derive(ToSchema)]
+use crate::config::{StorageConfig, UploadLimits};
+use crate::external_codecs::ExternalCodecs;
+use crate::handlers::{ApiError, ErrorResponse};
+use crate::naming::{new_storage_key, normalize_file_name};
+use crate::state::AppState;
+
+const ALGORITHM_FIELD: &str = "algorithm";
+const COMPRESSION_LEVEL_FIELD: &str = "compression_level";
+const DEFAULT_ALGORITHM: &str = "gzip";
+
+/// Why a file could not be saved.
+enum SaveError {
+    /// A size limit was exceeded, which fails the whole request.
+    TooLarge(String),
+    Failed(String),
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct UploadResponse {
-    pub success: Vec<String>,
+    pub success: Vec<UploadedFile>,
+    /// Why each of the other files was not uploaded.
     pub errors: Vec<String>,
 }
 
+#[derive(Serialize, ToSchema)]
+pub struct UploadedFile {
+    pub task_id: i32,
+    /// Name the file was uploaded as.
+    pub file_name: String,
+    /// Key the upload is stored under.
+    pub storage_key: String,
+    pub algorithm: String,
+    pub compression_level: u32,
+}
+
 #[utoipa::path(
     post,
     path = "/uploader/upload",
@@ -33,104 +61,416 @@ pub struct UploadResponse {
     responses(
         (status = 200, description = "Files uploaded successfully", body = UploadResponse),
         (status = 206, description = "Partial content - some files failed", body = UploadResponse),
-        (status = 400, description = "Bad request - no files provided")
+        (status = 400, description = "Bad request - malformed multipart body, no files provided or invalid compression settings", body = ErrorResponse),
+        (status = 413, description = "A file or the whole upload is larger than the configured limit", body = ErrorResponse),
+        (status = 422, description = "None of the files could be uploaded", body = UploadResponse)
     ),
     tag = "file-service"
 )]
 
 pub async fn upload_files(
-    mut multipart: Multipart,
-    // Extension(pool): Extension<PgPool>,
-) -> impl IntoResponse {
-    dotenv().ok();
-    let url = var("DATABASE_URL").unwrap();
-    let pool = PgPool::connect(&url).await.unwrap();
+    State(AppState {
+        config,
+        pool,
+        codecs,
+        ..
+    }): State<AppState>,
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<(StatusCode, Json<UploadResponse>), ApiError> {
+    let mut multipart = multipart?;
     let mut uploaded_files = Vec::new();
     let mut errors = Vec::new();
+    let mut saved_files = Vec::new();
+    let mut algorithm = None;
+    let mut compression_level = None;
+    let mut request_size = 0;
 
-    while let Ok(Some(field)) = multipart.next_field().await {
-        let file_name = match field.file_name() {
-            Some(name) => {
-                let timestamp = SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs();
-                format!("{}_{}", timestamp, name.replace(" ", "_"))
-            }
-            None => {
-                errors.push("Skipped field without filename".to_string());
-                continue;
+    // A malformed or oversized body fails the whole request
+    let read = async {
+        while let Some(mut field) = multipart
+            .next_field()
+            .await
+            .map_err(|e| multipart_error(e, &config.uploads))?
+        {
+            // Settings arrive as plain text fields and may come after the files
+            match field.name() {
+                Some(ALGORITHM_FIELD) => {
+                    algorithm = Some(
+                        field
+                            .text()
+                            .await
+                            .map_err(|e| multipart_error(e, &config.uploads))?,
+                    );
+                    continue;
+                }
+                Some(COMPRESSION_LEVEL_FIELD) => {
+                    compression_level = Some(
+                        field
+                            .text()
+                            .await
+                            .map_err(|e| multipart_error(e, &config.uploads))?,
+                    );
+                    continue;
+                }
+                _ => {}
             }
-        };
 
-        match field.bytes().await {
-            Ok(data) => {
-                // 1. Save file to disk
-                let save_path = PathBuf::from("uploads").join(&file_name);
-                if let Err(e) = fs::create_dir_all("uploads")
-                    .and_then(|_| File::create(&save_path))
-                    .and_then(|mut file| file.write_all(&data))
-                {
-                    errors.push(format!("Failed to save {}: {}", file_name, e));
+            // Files are stored under a generated key, and the client's name is
+            // only kept in the database
+            let original_name = match field.file_name().map(normalize_file_name) {
+                Some(Ok(name)) => name,
+                Some(Err(e)) => {
+                    errors.push(format!("Rejected file name: {}", e));
+                    continue;
+                }
+                None => {
+                    errors.push("Skipped field without filename".to_string());
                     continue;
                 }
+            };
+            let storage_key = new_storage_key();
 
-                // 2. Register in database
-                let row = match sqlx::query(
-                    "
-                    INSERT INTO compression_tasks (file_name, status)
-                    VALUES ($1, 'pending')
-                    RETURNING id
-                    ",
-                )
-                .bind(&file_name)
-                .fetch_one(&pool)
-                .await
-                {
-                    Ok(record) => {
-                        let id: i32 = record.get("id");
-                        uploaded_files.push(format!("{} (ID: {})", file_name, id));
-                    }
-                    Err(e) => {
-                        errors.push(format!("Failed to register {}: {}", file_name, e));
-                        // Clean up the file if DB registration failed
-                        let _ = fs::remove_file(save_path);
-                    }
-                };
+            // 1. Save file to disk
+            match save_field(
+                &mut field,
+                &original_name,
+                &storage_key,
+                &config.storage,
+                &config.uploads,
+                &mut request_size,
+            )
+            .await
+            {
+                Ok(save_path) => saved_files.push((original_name, storage_key, save_path)),
+                Err(SaveError::Failed(e)) => errors.push(e),
+                Err(SaveError::TooLarge(message)) => {
+                    return Err(ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, message));
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = read {
+        for (_, _, save_path) in saved_files {
+            let _ = fs::remove_file(save_path);
+        }
+        return Err(e);
+    }
+
+    let (algorithm, level) =
+        match resolve_settings(&codecs, algorithm.as_deref(), compression_level.as_deref()) {
+            Ok(settings) => settings,
+            Err(e) => {
+                for (_, _, save_path) in saved_files {
+                    let _ = fs::remove_file(save_path);
+                }
+                return Err(e.into());
+            }
+        };
+
+    // 2. Register in database
+    for (original_name, storage_key, save_path) in saved_files {
+        match register_upload(&pool, &storage_key, &original_name, algorithm, level).await {
+            Ok(task_id) => {
+                uploaded_files.push(UploadedFile {
+                    task_id,
+                    file_name: original_name,
+                    storage_key,
+                    algorithm: algorithm.to_string(),
+                    compression_level: level,
+                });
             }
             Err(e) => {
-                errors.push(format!("Failed to read file data: {}", e));
+                errors.push(format!("Failed to register {}: {}", original_name, e));
+                // Clean up the file if DB registration failed
+                let _ = fs::remove_file(save_path);
             }
+        };
+    }
+
+    let status = if uploaded_files.is_empty() && errors.is_empty() {
+        return Err(ApiError::bad_request("No files were uploaded"));
+    } else if uploaded_files.is_empty() {
+        StatusCode::UNPROCESSABLE_ENTITY
+    } else if !errors.is_empty() {
+        StatusCode::PARTIAL_CONTENT
+    } else {
+        StatusCode::OK
+    };
+
+    Ok((
+        status,
+        Json(UploadResponse {
+            success: uploaded_files,
+            errors,
+        }),
+    ))
+}
+
+/// Maps an error reading the multipart body to a response: `413` if the
+/// body went over the request limit, `400` otherwise.
+fn multipart_error(error: MultipartError, limits: &UploadLimits) -> ApiError {
+    // The body limit on the route is the request limit
+    if error.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!(
+                "Upload exceeds the limit of {} bytes per request",
+                limits.max_request_size
+            ),
+        )
+    } else {
+        ApiError::bad_request(format!("Invalid multipart body: {}", error))
+    }
+}
+
+/// Records an upload saved under `storage_key` as a pending compression
+/// task, returning the task ID.
+pub async fn register_upload(
+    executor: impl PgExecutor<'_>,
+    storage_key: &str,
+    original_name: &str,
+    algorithm: &str,
+    level: u32,
+) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar(
+        "
+        INSERT INTO compression_tasks (file_name, original_name, status, algorithm, compression_level)
+        VALUES ($1, $2, 'pending', $3, $4)
+        RETURNING id
+        ",
+    )
+    .bind(storage_key)
+    .bind(original_name)
+    .bind(algorithm)
+    .bind(level as i32)
+    .fetch_one(executor)
+    .await
+}
+
+/// Streams a file field to a partial file, then renames it into the uploads
+/// directory.
+/// `request_size` counts the file bytes received so far in the request.
+async fn save_field(
+    field: &mut Field<'_>,
+    original_name: &str,
+    storage_key: &str,
+    storage: &StorageConfig,
+    limits: &UploadLimits,
+    request_size: &mut u64,
+) -> Result<String, SaveError> {
+    let partial_path = storage.partial_dir.join(storage_key);
+    let save_path = storage.upload_path(storage_key);
+    let failed =
+        |e: std::io::Error| SaveError::Failed(format!("Failed to save {}: {}", original_name, e));
+
+    let mut file = tokio::fs::File::create(&partial_path)
+        .await
+        .map_err(failed)?;
+
+    let mut file_size = 0;
+    let result = async {
+        loop {
+            let chunk = match field.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                // The body limit on the route is the request limit
+                Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                    return Err(SaveError::TooLarge(format!(
+                        "Upload exceeds the limit of {} bytes per request",
+                        limits.max_request_size
+                    )));
+                }
+                Err(e) => {
+                    return Err(SaveError::Failed(format!(
+                        "Failed to read file data for {}: {}",
+                        original_name, e
+                    )));
+                }
+            };
+
+            file_size += chunk.len() as u64;
+            *request_size += chunk.len() as u64;
+            if file_size > limits.max_file_size {
+                return Err(SaveError::TooLarge(format!(
+                    "{} exceeds the limit of {} bytes per file",
+                    original_name, limits.max_file_size
+                )));
+            }
+            if *request_size > limits.max_request_size {
+                return Err(SaveError::TooLarge(format!(
+                    "Upload exceeds the limit of {} bytes per request",
+                    limits.max_request_size
+                )));
+            }
+
+            file.write_all(&chunk).await.map_err(failed)?;
         }
+        file.sync_all().await.map_err(failed)?;
+        drop(file);
+        tokio::fs::rename(&partial_path, &save_path)
+            .await
+            .map_err(failed)
     }
+    .await;
 
-    // Prepare response
-    let mut response = String::new();
-    if !uploaded_files.is_empty() {
-        response.push_str(&format!(
-            "Successfully uploaded:\n{}\n",
-            uploaded_files.join("\n")
-        ));
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&partial_path).await;
     }
-    if !errors.is_empty() {
-        response.push_str(&format!("\nErrors occurred:\n{}", errors.join("\n")));
+    result.map(|_| save_path)
+}
+
+/// Looks up the requested codec and checks the level against its range,
+/// falling back to gzip and the codec's default level. With `auto` the
+/// worker picks both later, so the level is stored as 0.
+pub fn resolve_settings(
+    codecs: &ExternalCodecs,
+    algorithm: Option<&str>,
+    compression_level: Option<&str>,
+) -> Result<(&'static str, u32), CompressionError> {
+    let algorithm = algorithm.map(str::trim).unwrap_or(DEFAULT_ALGORITHM);
+    if algorithm.eq_ignore_ascii_case(AUTO) {
+        return match compression_level {
+            Some(_) => Err(CompressionError::InvalidOption {
+                option: "level",
+                message: "a compression level cannot be combined with automatic selection"
+                    .to_string(),
+            }),
+            None => Ok((AUTO, 0)),
+        };
     }
 
-    if uploaded_files.is_empty() && errors.is_empty() {
-        (
-            StatusCode::BAD_REQUEST,
-            "No files were uploaded".to_string(),
-        )
-    } else if uploaded_files.is_empty() {
-        (StatusCode::PARTIAL_CONTENT, response)
-    } else {
-        (StatusCode::OK, response)
+    // Dictionary compression is zstd underneath and shares its levels
+    let dictionary = algorithm.eq_ignore_ascii_case(ZSTD_DICT);
+    let mut builder = codecs.with_codec_name(
+        CompressionOptions::builder(),
+        if dictionary { "zstd" } else { algorithm },
+    );
+    if let Some(level) = compression_level.map(str::trim) {
+        builder =
+            builder.level(
+                level
+                    .parse::<u32>()
+                    .map_err(|_| CompressionError::InvalidOption {
+                        option: "level",
+                        message: format!("{} is not a number", level),
+                    })?,
+            );
     }
+    let options = builder.build()?;
+    let (codec, level) = (options.codec(), options.level());
+
+    Ok((if dictionary { ZSTD_DICT } else { codec.name() }, level))
 }
 
-#[derive(utoipa::ToSchema)]
+#[derive(Serialize, ToSchema)]
 struct UploadRequest {
-    file: Vec<u8>,
     #[schema(value_type = String, format = Binary)]
+    file: Vec<u8>,
+    /// Compression algorithm, e.g. `gzip`, `zstd` or an external codec
+    /// configured on the server, `zstd-dict` for zstd with the latest
+    /// trained dictionary, or `auto` to let the server choose based on the
+    /// file contents. Defaults to `gzip`.
+    algorithm: Option<String>,
+    /// Compression level within the algorithm's range. Defaults to the
+    /// algorithm's default level.
     compression_level: Option<u32>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExternalCodecConfig;
+
+    fn invalid_option(error: CompressionError) -> &'static str {
+        match error {
+            CompressionError::InvalidOption { option, .. } => option,
+            e => panic!("expected an invalid option, got {}", e),
+        }
+    }
+
+    #[test]
+    fn test_settings_default_to_gzip() {
+        let codecs = ExternalCodecs::default();
+        assert_eq!(resolve_settings(&codecs, None, None).unwrap(), ("gzip", 6));
+        assert_eq!(
+            resolve_settings(&codecs, None, Some(" 9 ")).unwrap(),
+            ("gzip", 9)
+        );
+    }
+
+    #[test]
+    fn test_settings_check_level_against_codec() {
+        let codecs = ExternalCodecs::default();
+        assert_eq!(
+            resolve_settings(&codecs, Some(" ZSTD "), Some("19")).unwrap(),
+            ("zstd", 19)
+        );
+        let error = resolve_settings(&codecs, Some("gzip"), Some("19")).unwrap_err();
+        assert_eq!(invalid_option(error), "level");
+        let error = resolve_settings(&codecs, Some("gzip"), Some("fast")).unwrap_err();
+        assert_eq!(invalid_option(error), "level");
+    }
+
+    #[test]
+    fn test_settings_reject_unknown_codec() {
+        let codecs = ExternalCodecs::default();
+        let error = resolve_settings(&codecs, Some("rar"), None).unwrap_err();
+        assert_eq!(invalid_option(error), "codec");
+    }
+
+    #[test]
+    fn test_dictionary_settings_use_zstd_levels() {
+        let codecs = ExternalCodecs::default();
+        assert_eq!(
+            resolve_settings(&codecs, Some(ZSTD_DICT), Some("22")).unwrap(),
+            (ZSTD_DICT, 22)
+        );
+        assert!(resolve_settings(&codecs, Some(ZSTD_DICT), Some("23")).is_err());
+    }
+
+    #[test]
+    fn test_auto_settings_take_no_level() {
+        let codecs = ExternalCodecs::default();
+        assert_eq!(
+            resolve_settings(&codecs, Some("Auto"), None).unwrap(),
+            (AUTO, 0)
+        );
+        let error = resolve_settings(&codecs, Some(AUTO), Some("3")).unwrap_err();
+        assert_eq!(invalid_option(error), "level");
+    }
+
+    #[test]
+    fn test_settings_find_external_codecs() {
+        let configs = [(
+            "lzip".to_string(),
+            ExternalCodecConfig {
+                compress: "lzip -c -{level}".to_string(),
+                decompress: "lzip -d -c".to_string(),
+                levels: Some("0-9".to_string()),
+                default_level: Some(6),
+                ..Default::default()
+            },
+        )]
+        .into_iter()
+        .collect();
+        let codecs = ExternalCodecs::from_config(&configs).unwrap();
+
+        assert_eq!(
+            resolve_settings(&codecs, Some("LZIP"), None).unwrap(),
+            ("lzip", 6)
+        );
+        assert_eq!(
+            resolve_settings(&codecs, Some("lzip"), Some("0")).unwrap(),
+            ("lzip", 0)
+        );
+        let error = resolve_settings(&codecs, Some("lzip"), Some("10")).unwrap_err();
+        assert_eq!(invalid_option(error), "level");
+        // Built-in codecs are still found
+        assert_eq!(
+            resolve_settings(&codecs, Some("gzip"), None).unwrap(),
+            ("gzip", 6)
+        );
+    }
+}
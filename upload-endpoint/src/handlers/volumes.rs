@@ -0,0 +1,120 @@
+use axum::{
+    extract::{rejection::PathRejection, Path, State},
+    Json,
+};
+use file_compression::{dictionary::ZSTD_DICT, manifest_path, VolumeManifest};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::{fs::File, io::BufReader};
+
+use utoipa::ToSchema;
+
+use crate::handlers::{ApiError, ErrorResponse};
+use crate::state::AppState;
+
+/// A task whose output may have been split into volumes.
+#[derive(FromRow)]
+struct SplitTask {
+    file_name: String,
+    algorithm: String,
+    volume_count: Option<i32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct VolumeDownload {
+    /// Where to download this volume.
+    pub url: String,
+    pub size: u64,
+    /// Hex SHA-256 of the volume.
+    pub sha256: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct VolumesResponse {
+    /// Where to download the manifest listing the volumes in order.
+    pub manifest_url: String,
+    pub codec: String,
+    /// Volumes in the order they must be joined.
+    pub volumes: Vec<VolumeDownload>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/compressor/volumes/{task_id}",
+    params(
+        ("task_id" = i32, Path, description = "Task ID whose volumes to list")
+    ),
+    responses(
+        (status = 200, description = "Volumes of the task's output, in order", body = VolumesResponse),
+        (status = 404, description = "Task not found or its output was not split", body = ErrorResponse),
+        (status = 500, description = "Internal server error", body = ErrorResponse)
+    ),
+    tag = "file-service"
+)]
+
+pub async fn list_volumes(
+    task_id: Result<Path<i32>, PathRejection>,
+    State(AppState {
+        config,
+        pool,
+        codecs,
+        ..
+    }): State<AppState>,
+) -> Result<Json<VolumesResponse>, ApiError> {
+    let Path(task_id) = task_id?;
+    let task = sqlx::query_as::<_, SplitTask>(
+        "SELECT file_name, algorithm, volume_count FROM compression_tasks
+         WHERE id = $1 AND status = 'completed'",
+    )
+    .bind(task_id)
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::RowNotFound => {
+            ApiError::not_found(format!("Completed task with ID {} not found", task_id))
+        }
+        e => ApiError::database(e),
+    })?;
+    if task.volume_count.is_none() {
+        return Err(ApiError::not_found(format!(
+            "Output of task {} was not split into volumes",
+            task_id
+        )));
+    }
+
+    // Dictionary outputs are zstd frames and share its extension
+    let name = if task.algorithm == ZSTD_DICT {
+        "zstd"
+    } else {
+        &task.algorithm
+    };
+    let extension = codecs
+        .codec_by_name(name)
+        .ok_or_else(|| {
+            ApiError::internal(format!(
+                "Unsupported compression algorithm: {}",
+                task.algorithm
+            ))
+        })?
+        .extension();
+
+    let manifest_name = manifest_path(&format!("{}.{}", task.file_name, extension));
+    let manifest = File::open(config.storage.compressed_path(&manifest_name))
+        .and_then(|file| VolumeManifest::read_from(BufReader::new(file)))
+        .map_err(|e| ApiError::internal(format!("Failed to read volume manifest: {}", e)))?;
+
+    // Each volume is served on its own by the compressed files route
+    Ok(Json(VolumesResponse {
+        manifest_url: format!("/compressor/files/{}", manifest_name),
+        codec: manifest.codec.to_string(),
+        volumes: manifest
+            .volumes
+            .iter()
+            .map(|volume| VolumeDownload {
+                url: format!("/compressor/files/{}", volume.file_name),
+                size: volume.size,
+                sha256: volume.sha256.clone(),
+            })
+            .collect(),
+    }))
+}
@@ -1,20 +1,27 @@
+mod config;
 mod db;
+mod external_codecs;
 mod handlers;
+mod naming;
 mod openapi;
+mod state;
 
 use axum::{
-    extract::{Path, Query},
+    extract::{DefaultBodyLimit, Path, Query},
     response::IntoResponse,
     routing::{get, post},
-    Extension, Router,
+    Router,
 };
+use config::Config;
 use db::establish_connection;
-use handlers::{check, compress_file, upload_file};
+use external_codecs::ExternalCodecs;
+use handlers::{check, compress_file, dictionary, downloads, transcode, tus, upload_file, volumes};
 use openapi::ApiDoc;
 use serde::Deserialize;
-use std::net::SocketAddr;
+use state::AppState;
+use std::sync::Arc;
 use tokio::net::TcpListener;
-use tower_http::{services::ServeDir, trace::TraceLayer};
+use tower_http::trace::TraceLayer;
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
@@ -22,8 +29,16 @@ use utoipa_swagger_ui::SwaggerUi;
 async fn main() {
     dotenv::dotenv().ok();
 
+    let config = match Config::load() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            return;
+        }
+    };
+
     // Initialize database connection
-    let pool = match establish_connection().await {
+    let pool = match establish_connection(&config.database).await {
         Ok(pool) => pool,
         Err(e) => {
             eprintln!("Failed to connect to database: {}", e);
@@ -31,21 +46,53 @@ async fn main() {
         }
     };
 
+    // Codecs that run external programs, offered next to the built-in ones
+    let codecs = match ExternalCodecs::from_config(&config.external_codecs) {
+        Ok(codecs) => Arc::new(codecs),
+        Err(e) => {
+            eprintln!("Failed to load external codecs: {}", e);
+            return;
+        }
+    };
+    if !codecs.names().is_empty() {
+        println!("External codecs: {}", codecs.names().join(", "));
+    }
+
+    let state = AppState {
+        config: config.clone(),
+        pool,
+        codecs,
+        jobs: compress_file::RunningJobs::default(),
+        tus_uploads: tus::ActiveUploads::default(),
+    };
+
     // Compression service routes
     let compressor = Router::new()
         .route("/compress", post(compress_file::compress_all_files))
-        .nest_service("/files", ServeDir::new("compressed"))
-        .layer(Extension(pool.clone()));
+        .route("/dictionary", post(dictionary::train_dictionary))
+        .route("/cancel/{task_id}", post(compress_file::cancel_task))
+        .route("/transcode", post(transcode::transcode_completed))
+        .route("/volumes/{task_id}", get(volumes::list_volumes))
+        .merge(downloads::serve_files(
+            &config.storage.compressed_dir,
+            state.clone(),
+        ));
 
     // Upload service routes
     let uploads = Router::new()
-        .route("/upload", post(upload_file::upload_files))
-        .nest_service("/files", ServeDir::new("uploads"))
-        .layer(Extension(pool.clone()));
+        .route(
+            "/upload",
+            post(upload_file::upload_files).layer(DefaultBodyLimit::max(
+                usize::try_from(config.uploads.max_request_size).unwrap_or(usize::MAX),
+            )),
+        )
+        .nest("/tus", tus::routes())
+        .merge(downloads::serve_files(
+            &config.storage.uploads_dir,
+            state.clone(),
+        ));
 
-    let status_check: Router = Router::new()
-        .route("/{task_id}", get(check::check_status))
-        .layer(Extension(pool.clone()));
+    let status_check = Router::new().route("/{task_id}", get(check::check_status));
 
     // Main API router
     let app = Router::new()
@@ -55,11 +102,11 @@ async fn main() {
         .nest("/compressor", compressor)
         .nest("/check", status_check)
         .merge(SwaggerUi::new("/swagger-ui").url("/api-doc/openapi.json", ApiDoc::openapi()))
-        .fallback(|| async { r#"{"status":404,"message":"Resource Not Found"}"# })
+        .fallback(handlers::not_found)
         .layer(TraceLayer::new_for_http())
-        .layer(Extension(pool));
+        .with_state(state);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    let addr = config.server.addr();
     println!("Server running on http://{}", addr);
 
     // Start server and await it
@@ -67,6 +114,8 @@ async fn main() {
     axum::serve(listener, app).await.unwrap();
 }
 
+// Fields are only read through the Debug output below
+#[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct MyQuery {
     gis: Option<bool>,
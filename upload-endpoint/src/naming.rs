@@ -0,0 +1,143 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+/// Longest original file name accepted, in bytes, as on most file systems.
+const MAX_NAME_LEN: usize = 255;
+
+/// Characters left as-is in an RFC 5987 `ext-value`, its `attr-char`.
+const ATTR_CHAR: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// A new key to store an upload under. UUIDv7 keys start with a timestamp,
+/// so they sort by upload time.
+pub fn new_storage_key() -> String {
+    Uuid::now_v7().to_string()
+}
+
+/// Normalizes a client file name to NFC and checks that it is a plain file
+/// name: not empty, `.` or `..`, without path separators, control or
+/// bidirectional formatting characters, and at most 255 bytes.
+pub fn normalize_file_name(name: &str) -> Result<String, String> {
+    let name: String = name.nfc().collect();
+
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("\"{}\" is not a file name", name));
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(format!("name is longer than {} bytes", MAX_NAME_LEN));
+    }
+    if let Some(c) = name.chars().find(|&c| {
+        c == '/'
+            || c == '\\'
+            || c.is_control()
+            || matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+    }) {
+        return Err(format!(
+            "name contains the forbidden character {}",
+            c.escape_unicode()
+        ));
+    }
+
+    Ok(name)
+}
+
+/// `Content-Disposition` value offering `file_name` as a download, with an
+/// ASCII fallback for clients that do not understand RFC 5987.
+pub fn content_disposition(file_name: &str) -> String {
+    let fallback: String = file_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        fallback,
+        utf8_percent_encode(file_name, ATTR_CHAR)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_names_are_kept() {
+        assert_eq!(normalize_file_name("report.pdf").unwrap(), "report.pdf");
+        assert_eq!(normalize_file_name("..hidden").unwrap(), "..hidden");
+    }
+
+    #[test]
+    fn test_names_are_normalized_to_nfc() {
+        // "e" followed by a combining acute accent
+        let decomposed = "cafe\u{301}.txt";
+        assert_eq!(normalize_file_name(decomposed).unwrap(), "caf\u{e9}.txt");
+    }
+
+    #[test]
+    fn test_paths_and_special_names_are_rejected() {
+        for name in ["", ".", "..", "../etc/passwd", "dir/file", "dir\\file"] {
+            assert!(normalize_file_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_control_and_bidi_characters_are_rejected() {
+        for name in [
+            "a\0b",
+            "line\nbreak",
+            "tab\t",
+            "\u{7f}",
+            "invoice\u{202E}fdp.exe",
+            "\u{2066}isolate\u{2069}",
+        ] {
+            assert!(normalize_file_name(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_name_length_is_limited_in_bytes() {
+        assert!(normalize_file_name(&"a".repeat(MAX_NAME_LEN)).is_ok());
+        assert!(normalize_file_name(&"a".repeat(MAX_NAME_LEN + 1)).is_err());
+        // 128 two-byte characters are 256 bytes
+        assert!(normalize_file_name(&"\u{e9}".repeat(128)).is_err());
+    }
+
+    #[test]
+    fn test_content_disposition_of_ascii_name() {
+        assert_eq!(
+            content_disposition("report.pdf.gz"),
+            "attachment; filename=\"report.pdf.gz\"; filename*=UTF-8''report.pdf.gz"
+        );
+    }
+
+    #[test]
+    fn test_content_disposition_encodes_per_rfc_5987() {
+        assert_eq!(
+            content_disposition("Résumé \"final\".pdf"),
+            "attachment; filename=\"R_sum_ _final_.pdf\"; \
+             filename*=UTF-8''R%C3%A9sum%C3%A9%20%22final%22.pdf"
+        );
+        assert_eq!(
+            content_disposition("a\\b;c"),
+            "attachment; filename=\"a_b;c\"; filename*=UTF-8''a%5Cb%3Bc"
+        );
+    }
+}
@@ -1,19 +1,33 @@
-use crate::handlers;
 use utoipa::OpenApi;
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         crate::handlers::upload_file::upload_files,
+        crate::handlers::tus::tus_options,
+        crate::handlers::tus::create_upload,
+        crate::handlers::tus::upload_offset,
+        crate::handlers::tus::append_upload,
+        crate::handlers::tus::terminate_upload,
         crate::handlers::compress_file::compress_all_files,
+        crate::handlers::compress_file::cancel_task,
+        crate::handlers::dictionary::train_dictionary,
+        crate::handlers::transcode::transcode_completed,
+        crate::handlers::volumes::list_volumes,
         crate::handlers::check::check_status,
     ),
     components(
         schemas(
             crate::handlers::upload_file::UploadResponse,
+            crate::handlers::upload_file::UploadedFile,
             crate::handlers::compress_file::CompressionResponse,
+            crate::handlers::compress_file::CancelResponse,
+            crate::handlers::dictionary::DictionaryResponse,
+            crate::handlers::transcode::TranscodeResponse,
+            crate::handlers::volumes::VolumesResponse,
+            crate::handlers::volumes::VolumeDownload,
             crate::handlers::check::StatusResponse,
-            crate::handlers::check::ErrorResponse
+            crate::handlers::ErrorResponse
         )
     ),
     tags(
@@ -0,0 +1,20 @@
+use sqlx::PgPool;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::external_codecs::ExternalCodecs;
+use crate::handlers::compress_file::RunningJobs;
+use crate::handlers::tus::ActiveUploads;
+
+/// Shared by every handler through axum's `State`.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub pool: PgPool,
+    /// Codecs that run external programs, built from the configuration.
+    pub codecs: Arc<ExternalCodecs>,
+    /// Compression jobs in flight, shared with the status check.
+    pub jobs: RunningJobs,
+    /// Resumable uploads a request is currently changing.
+    pub tus_uploads: ActiveUploads,
+}